@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use random_tree_node::{ArenaRandomTree, RandomTree};
+
+const LEN: u32 = 1_000_000;
+
+// Inserts in random order, so that the boxed nodes end up scattered the way
+// they would be after a long run of inserts.
+fn shuffled_keys() -> Vec<u32> {
+    let mut keys = (0..LEN).collect::<Vec<_>>();
+    keys.shuffle(&mut StdRng::seed_from_u64(0));
+    keys
+}
+
+fn random_draws(c: &mut Criterion) {
+    let keys = shuffled_keys();
+    let mut boxed = RandomTree::new();
+    let mut arena = ArenaRandomTree::new();
+    for key in keys {
+        boxed.insert(key, key);
+        arena.insert(key, key);
+    }
+
+    let mut group = c.benchmark_group("random_draws_1m");
+    group.bench_function("boxed", |b| {
+        let mut rng = StdRng::seed_from_u64(1);
+        b.iter(|| boxed.random(&mut rng))
+    });
+    group.bench_function("arena", |b| {
+        let mut rng = StdRng::seed_from_u64(1);
+        b.iter(|| arena.random(&mut rng))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, random_draws);
+criterion_main!(benches);
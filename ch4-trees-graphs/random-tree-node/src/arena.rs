@@ -0,0 +1,1372 @@
+use crate::{
+    iter::{InOrder, Split},
+    DELTA, GAMMA,
+};
+use rand::{seq::index, Rng};
+use std::{
+    borrow::Borrow,
+    cmp::{Ordering, Reverse},
+    iter::{FromIterator, FusedIterator},
+    ops::{Index, RangeBounds},
+};
+
+// Stands in for None.
+const NIL: u32 = u32::MAX;
+
+struct ArenaNode<K, V> {
+    key: K,
+    value: V,
+    size: u32, // Same as Node::size. Fits in a u32, like the links.
+    left: u32,
+    right: u32,
+}
+
+impl<K, V> ArenaNode<K, V> {
+    fn new(key: K, value: V) -> Self {
+        Self {
+            key,
+            value,
+            size: 1,
+            left: NIL,
+            right: NIL,
+        }
+    }
+}
+
+// Same as RandomTree, but all nodes live in one Vec and link to each other
+// by index. That keeps them close together in memory, which makes the
+// descent in random cheaper on large trees. To keep them small, nodes hold
+// no summary, only their size.
+// The Vec is kept dense: removing a node moves the last one into its slot.
+// Links can't hand out nodes mutably or by value in key order, so iter_mut,
+// into_iter and merging appends first sort the Vec, in O(n). Rotations
+// never move a node to another slot, though.
+// Only part of RandomTree's API is supported: the map itself with entries,
+// order statistics, ranges, uniform draws and samples, split_off, append
+// and join, and in-order iterators. There are no summaries or weighted
+// draws, cursors, neighbour lookups (floor, first_key_value, pop_first and
+// the like), random pops or random_where, bulk removal (retain, extract_if,
+// remove_all), or level-order and shuffled iterators; use RandomTree for
+// those.
+pub struct ArenaRandomTree<K, V> {
+    nodes: Vec<ArenaNode<K, V>>,
+    root: u32,
+}
+
+impl<K, V> ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            root: NIL,
+        }
+    }
+
+    // Same as RandomTree::from_sorted_iter.
+    // Panics if the keys are out of order.
+    // O(n)
+    pub fn from_sorted_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries = iter.into_iter().collect::<Vec<_>>();
+        assert!(
+            entries.windows(2).all(|pair| pair[0].0 <= pair[1].0),
+            "keys are not sorted"
+        );
+        crate::dedup_sorted(&mut entries);
+        Self::from_sorted_vec(entries)
+    }
+
+    // O(1)
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Returns None iff the tree is empty.
+    // O(log(n))
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+        self.select(rng.gen_range(0, len))
+    }
+
+    // Picks min(amount, len) distinct entries, in random order.
+    // O(amount * log(n))
+    pub fn sample<R: Rng + ?Sized>(&self, amount: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        let len = self.len();
+        index::sample(rng, len, amount.min(len))
+            .into_iter()
+            .map(|i| self.select(i).unwrap())
+            .collect()
+    }
+
+    // Same as sample, but the entries come sorted by key.
+    // O(amount * log(n))
+    pub fn sample_sorted<R: Rng + ?Sized>(&self, amount: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        let len = self.len();
+        let mut ranks = index::sample(rng, len, amount.min(len)).into_vec();
+        ranks.sort_unstable();
+        ranks.into_iter().map(|i| self.select(i).unwrap()).collect()
+    }
+
+    // Picks uniformly among the keys inside range.
+    // Returns None iff there are none.
+    // O(log(n))
+    pub fn random_in_range<T, R>(&self, range: T, rng: &mut R) -> Option<(&K, &V)>
+    where
+        T: RangeBounds<K>,
+        R: Rng + ?Sized,
+    {
+        let (start, end) = self.index_bounds(&range);
+        if start < end {
+            self.select(rng.gen_range(start, end))
+        } else {
+            None
+        }
+    }
+
+    // Number of keys inside range.
+    // O(log(n))
+    pub fn count_range<T: RangeBounds<K>>(&self, range: T) -> usize {
+        let (start, end) = self.index_bounds(&range);
+        end.saturating_sub(start)
+    }
+
+    // Returns the old value associated with K, or None if the key is new.
+    // O(log(n))
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (root, old) = self.insert_aux(self.root, key, value);
+        self.root = root;
+        old
+    }
+
+    // Gets the entry for key, to insert or update it in place.
+    // O(log(n)), and O(log(n)) more to insert into a vacant entry.
+    pub fn entry(&mut self, key: K) -> ArenaEntry<'_, K, V> {
+        match self.find(&key) {
+            Some(index) => ArenaEntry::Occupied(ArenaOccupiedEntry { tree: self, index }),
+            None => ArenaEntry::Vacant(ArenaVacantEntry { tree: self, key }),
+        }
+    }
+
+    // O(log(n))
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).map(|i| &self.nodes[i as usize].value)
+    }
+
+    // O(log(n))
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let i = self.find(key)?;
+        Some(&mut self.nodes[i as usize].value)
+    }
+
+    // O(log(n))
+    pub fn has<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    // Returns the value associated with K, or None if the key was absent.
+    // O(log(n))
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (root, removed) = self.remove_by(self.root, &mut |_, node| key.cmp(node.key.borrow()));
+        self.root = root;
+        removed.map(|i| self.free(i).1)
+    }
+
+    // Removes and returns the i-th smallest entry.
+    // O(log(n))
+    pub fn remove_index(&mut self, mut i: usize) -> Option<(K, V)> {
+        if i >= self.len() {
+            return None;
+        }
+        let (root, removed) = self.remove_by(self.root, &mut |tree, node| {
+            let left = tree.size(node.left);
+            let ordering = i.cmp(&left);
+            if ordering == Ordering::Greater {
+                i -= left + 1;
+            }
+            ordering
+        });
+        self.root = root;
+        removed.map(|i| self.free(i))
+    }
+
+    // Returns the i-th smallest entry, counting from 0.
+    // O(log(n))
+    pub fn select(&self, i: usize) -> Option<(&K, &V)> {
+        let node = &self.nodes[self.find_index(i)? as usize];
+        Some((&node.key, &node.value))
+    }
+
+    // Same as select, but the value is mutable.
+    // O(log(n))
+    pub fn get_index_mut(&mut self, i: usize) -> Option<(&K, &mut V)> {
+        let i = self.find_index(i)?;
+        let node = &mut self.nodes[i as usize];
+        Some((&node.key, &mut node.value))
+    }
+
+    // Returns Ok with the position of key in sorted order if it is present,
+    // or Err with the position it would be inserted at.
+    // O(log(n))
+    pub fn rank<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut smaller = 0;
+        let mut current = self.root;
+        while current != NIL {
+            let node = &self.nodes[current as usize];
+            match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Ok(smaller + self.size(node.left)),
+                Ordering::Less => current = node.left,
+                Ordering::Greater => {
+                    smaller += self.size(node.left) + 1;
+                    current = node.right;
+                }
+            }
+        }
+        Err(smaller)
+    }
+
+    // Moves every key greater than or equal to key into a new tree.
+    // O(log(n) + k * log(n)), where k is the size of the smaller half: the
+    // links are split in O(log(n)) like RandomTree's, but each tree needs a
+    // Vec of its own, so the nodes of one half have to be moved out.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (smaller, rest) = self.split(self.root, key);
+        if self.size(smaller) >= self.size(rest) {
+            self.extract(smaller, rest)
+        } else {
+            let smaller = self.extract(rest, smaller);
+            std::mem::replace(self, smaller)
+        }
+    }
+
+    // Moves every entry of other into self, leaving other empty. On equal
+    // keys, the value from other wins.
+    // O(m + log(n)) if the key ranges don't overlap, where m is the length
+    // of other, whose nodes are moved over. O(n + m) otherwise.
+    pub fn append(&mut self, other: &mut Self) {
+        let mut other = std::mem::take(other);
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+
+        if self.rightmost().key < other.leftmost().key {
+            let right = self.absorb(other);
+            self.root = self.concat(self.root, right);
+        } else if other.rightmost().key < self.leftmost().key {
+            let left = self.absorb(other);
+            self.root = self.concat(left, self.root);
+        } else {
+            self.sort_nodes();
+            other.sort_nodes();
+            let (left, right) = (std::mem::take(&mut self.nodes), other.nodes);
+            let mut merged = Vec::with_capacity(left.len() + right.len());
+            let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+            while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+                match l.key.cmp(&r.key) {
+                    Ordering::Less => merged.push(left.next().unwrap()),
+                    Ordering::Greater => merged.push(right.next().unwrap()),
+                    Ordering::Equal => {
+                        left.next();
+                        merged.push(right.next().unwrap());
+                    }
+                }
+            }
+            merged.extend(left.chain(right));
+            self.nodes = merged;
+            self.root = self.link_sorted(0, self.len());
+        }
+    }
+
+    // Concatenates two trees. Every key in left has to be smaller than every
+    // key in right.
+    // Panics if the key ranges overlap.
+    // O(m + log(n)), where m is the length of right, whose nodes are moved
+    // over.
+    pub fn join(mut left: Self, right: Self) -> Self {
+        if !left.is_empty() && !right.is_empty() {
+            assert!(
+                left.rightmost().key < right.leftmost().key,
+                "key ranges overlap"
+            );
+        }
+        let right = left.absorb(right);
+        left.root = left.concat(left.root, right);
+        left
+    }
+
+    // Iterates in key order.
+    pub fn iter(&self) -> ArenaIter<'_, K, V> {
+        ArenaIter {
+            inner: InOrder::new(self.root_ref()),
+        }
+    }
+
+    // Same as iter, but the values are mutable.
+    // O(n) to set up, since the nodes are sorted first.
+    pub fn iter_mut(&mut self) -> ArenaIterMut<'_, K, V> {
+        self.sort_nodes();
+        ArenaIterMut {
+            inner: self.nodes.iter_mut(),
+        }
+    }
+
+    pub fn keys(&self) -> ArenaKeys<'_, K, V> {
+        ArenaKeys { inner: self.iter() }
+    }
+
+    pub fn values(&self) -> ArenaValues<'_, K, V> {
+        ArenaValues { inner: self.iter() }
+    }
+
+    // O(n) to set up, like iter_mut.
+    pub fn values_mut(&mut self) -> ArenaValuesMut<'_, K, V> {
+        ArenaValuesMut {
+            inner: self.iter_mut(),
+        }
+    }
+
+    // Iterates in key order over the keys inside range.
+    // O(log(n)) to set up.
+    pub fn range<T: RangeBounds<K>>(&self, range: T) -> ArenaIter<'_, K, V> {
+        let (start, end) = self.index_bounds(&range);
+        ArenaIter {
+            inner: InOrder::range(self.root_ref(), start, end),
+        }
+    }
+
+    fn root_ref(&self) -> Option<ArenaRef<'_, K, V>> {
+        Some(ArenaRef {
+            nodes: &self.nodes,
+            index: self.root,
+        })
+        .filter(|root| root.index != NIL)
+    }
+
+    fn size(&self, i: u32) -> usize {
+        match i {
+            NIL => 0,
+            i => self.nodes[i as usize].size as usize,
+        }
+    }
+
+    // Same as Node::left_balance_weight and friends.
+    fn balance_weight(&self, i: u32) -> usize {
+        self.size(i) + 1
+    }
+
+    // Same as Node::update_counts.
+    fn update_counts(&mut self, i: u32) {
+        let node = &self.nodes[i as usize];
+        let size = self.size(node.left) + 1 + self.size(node.right);
+        self.nodes[i as usize].size = size as u32;
+    }
+
+    fn rotate_left(&mut self, i: u32) -> u32 {
+        let new_root = self.nodes[i as usize].right;
+        self.nodes[i as usize].right = self.nodes[new_root as usize].left;
+        self.update_counts(i);
+        self.nodes[new_root as usize].left = i;
+        self.update_counts(new_root);
+        new_root
+    }
+
+    fn rotate_right(&mut self, i: u32) -> u32 {
+        let new_root = self.nodes[i as usize].left;
+        self.nodes[i as usize].left = self.nodes[new_root as usize].right;
+        self.update_counts(i);
+        self.nodes[new_root as usize].right = i;
+        self.update_counts(new_root);
+        new_root
+    }
+
+    // Same as Node::balance. Returns the new root of the subtree.
+    fn balance(&mut self, i: u32) -> u32 {
+        let (left, right) = (self.nodes[i as usize].left, self.nodes[i as usize].right);
+        if self.balance_weight(right) > DELTA * self.balance_weight(left) {
+            let child = &self.nodes[right as usize];
+            if self.balance_weight(child.left) >= GAMMA * self.balance_weight(child.right) {
+                self.nodes[i as usize].right = self.rotate_right(right);
+            }
+            self.rotate_left(i)
+        } else if self.balance_weight(left) > DELTA * self.balance_weight(right) {
+            let child = &self.nodes[left as usize];
+            if self.balance_weight(child.right) >= GAMMA * self.balance_weight(child.left) {
+                self.nodes[i as usize].left = self.rotate_left(left);
+            }
+            self.rotate_right(i)
+        } else {
+            i
+        }
+    }
+
+    // Same as Node::link. Returns the index of the new subtree's root.
+    fn link(&mut self, left: u32, node: u32, right: u32) -> u32 {
+        let (left_weight, right_weight) = (self.balance_weight(left), self.balance_weight(right));
+        if left_weight > DELTA * right_weight {
+            let child = self.nodes[left as usize].right;
+            self.nodes[left as usize].right = self.link(child, node, right);
+            self.update_counts(left);
+            self.balance(left)
+        } else if right_weight > DELTA * left_weight {
+            let child = self.nodes[right as usize].left;
+            self.nodes[right as usize].left = self.link(left, node, child);
+            self.update_counts(right);
+            self.balance(right)
+        } else {
+            self.nodes[node as usize].left = left;
+            self.nodes[node as usize].right = right;
+            self.update_counts(node);
+            node
+        }
+    }
+
+    // Same as Node::concat.
+    fn concat(&mut self, left: u32, right: u32) -> u32 {
+        if right == NIL {
+            return left;
+        }
+        let (min, rest) = self.pop_min(right);
+        self.link(left, min, rest)
+    }
+
+    // Same as Node::split, on the subtree at i. Returns the roots of both
+    // halves, which still share the arena.
+    fn split<Q>(&mut self, i: u32, key: &Q) -> (u32, u32)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        if i == NIL {
+            return (NIL, NIL);
+        }
+        let node = &self.nodes[i as usize];
+        let (left, right) = (node.left, node.right);
+        if key <= node.key.borrow() {
+            let (smaller, rest) = self.split(left, key);
+            (smaller, self.link(rest, i, right))
+        } else {
+            let (smaller, rest) = self.split(right, key);
+            (self.link(left, i, smaller), rest)
+        }
+    }
+
+    // Returns the new root of the subtree, and the old value if key was
+    // already present. A new node always goes to the end of the Vec.
+    fn insert_aux(&mut self, i: u32, key: K, value: V) -> (u32, Option<V>) {
+        if i == NIL {
+            assert!(self.nodes.len() < NIL as usize, "arena is full");
+            let node = ArenaNode::new(key, value);
+            self.nodes.push(node);
+            return ((self.nodes.len() - 1) as u32, None);
+        }
+
+        let old = match key.cmp(&self.nodes[i as usize].key) {
+            Ordering::Less => {
+                let left = self.nodes[i as usize].left;
+                let (left, old) = self.insert_aux(left, key, value);
+                self.nodes[i as usize].left = left;
+                old
+            }
+            Ordering::Greater => {
+                let right = self.nodes[i as usize].right;
+                let (right, old) = self.insert_aux(right, key, value);
+                self.nodes[i as usize].right = right;
+                old
+            }
+            Ordering::Equal => Some(std::mem::replace(&mut self.nodes[i as usize].value, value)),
+        };
+        self.update_counts(i);
+        (self.balance(i), old)
+    }
+
+    // Same as RandomTree::remove_by, with locate also given the tree.
+    // Returns the new root of the subtree, and the index of the unlinked
+    // node, which is still taking up its slot.
+    fn remove_by<F>(&mut self, i: u32, locate: &mut F) -> (u32, Option<u32>)
+    where
+        F: FnMut(&Self, &ArenaNode<K, V>) -> Ordering,
+    {
+        if i == NIL {
+            return (NIL, None);
+        }
+        let node = &self.nodes[i as usize];
+        let (left, right) = (node.left, node.right);
+        let removed = match locate(self, node) {
+            Ordering::Equal => return (self.splice(left, right), Some(i)),
+            Ordering::Less => {
+                let (left, removed) = self.remove_by(left, locate);
+                self.nodes[i as usize].left = left;
+                removed
+            }
+            Ordering::Greater => {
+                let (right, removed) = self.remove_by(right, locate);
+                self.nodes[i as usize].right = right;
+                removed
+            }
+        };
+        if removed.is_none() {
+            return (i, None);
+        }
+        self.update_counts(i);
+        (self.balance(i), removed)
+    }
+
+    // Same as Node::pop_min. Returns the index of the minimum, and the new
+    // root of the rest of the subtree.
+    fn pop_min(&mut self, i: u32) -> (u32, u32) {
+        let left = self.nodes[i as usize].left;
+        if left == NIL {
+            let node = &mut self.nodes[i as usize];
+            let rest = node.right;
+            node.right = NIL;
+            node.size = 1;
+            return (i, rest);
+        }
+        let (min, rest) = self.pop_min(left);
+        self.nodes[i as usize].left = rest;
+        self.update_counts(i);
+        (min, self.balance(i))
+    }
+
+    // Same as Node::splice.
+    fn splice(&mut self, left: u32, right: u32) -> u32 {
+        if left == NIL {
+            return right;
+        }
+        if right == NIL {
+            return left;
+        }
+        let (successor, rest) = self.pop_min(right);
+        self.nodes[successor as usize].left = left;
+        self.nodes[successor as usize].right = rest;
+        self.update_counts(successor);
+        self.balance(successor)
+    }
+
+    // Takes an unlinked node out of the arena, moving the last node into
+    // its slot and pointing its parent at the new index.
+    fn free(&mut self, i: u32) -> (K, V) {
+        let last = (self.nodes.len() - 1) as u32;
+        if i != last {
+            let parent_link = self.link_to(last);
+            *parent_link = i;
+        }
+        let node = self.nodes.swap_remove(i as usize);
+        (node.key, node.value)
+    }
+
+    // The link that points at the node with index i.
+    fn link_to(&mut self, i: u32) -> &mut u32 {
+        if self.root == i {
+            return &mut self.root;
+        }
+        let mut current = self.root;
+        loop {
+            let node = &self.nodes[current as usize];
+            let ordering = self.nodes[i as usize].key.cmp(&node.key);
+            let next = match ordering {
+                Ordering::Less => node.left,
+                _ => node.right,
+            };
+            if next == i {
+                let node = &mut self.nodes[current as usize];
+                return match ordering {
+                    Ordering::Less => &mut node.left,
+                    _ => &mut node.right,
+                };
+            }
+            current = next;
+        }
+    }
+
+    // Moves the nodes of the subtree at moved into a tree of their own, and
+    // leaves the one at kept as this tree. Each slot freed up is refilled
+    // from the end, like in free, going down from the highest so that the
+    // node moved into it is always one that is kept.
+    // O(k * log(n)), where k is the size of the subtree at moved.
+    fn extract(&mut self, kept: u32, moved: u32) -> Self {
+        let mut slots = Vec::with_capacity(self.size(moved));
+        self.collect_slots(moved, &mut slots);
+        let mut ranked = slots.into_iter().enumerate().collect::<Vec<_>>();
+        ranked.sort_unstable_by_key(|&(_, i)| Reverse(i));
+
+        self.root = kept;
+        let mut nodes = (0..ranked.len()).map(|_| None).collect::<Vec<_>>();
+        for (rank, i) in ranked {
+            let last = (self.nodes.len() - 1) as u32;
+            if i != last {
+                *self.link_to(last) = i;
+            }
+            nodes[rank] = Some(self.nodes.swap_remove(i as usize));
+        }
+        let mut tree = Self {
+            nodes: nodes.into_iter().map(Option::unwrap).collect(),
+            root: NIL,
+        };
+        tree.root = tree.link_sorted(0, tree.len());
+        tree
+    }
+
+    // Pushes the indices of the subtree at i onto slots, in key order.
+    fn collect_slots(&self, i: u32, slots: &mut Vec<u32>) {
+        if i == NIL {
+            return;
+        }
+        let node = &self.nodes[i as usize];
+        self.collect_slots(node.left, slots);
+        slots.push(i);
+        self.collect_slots(node.right, slots);
+    }
+
+    // Moves the nodes of other to the end of this arena, without linking
+    // them in. Returns the index its root ends up at.
+    fn absorb(&mut self, other: Self) -> u32 {
+        assert!(
+            self.nodes.len() + other.nodes.len() < NIL as usize,
+            "arena is full"
+        );
+        let offset = self.nodes.len() as u32;
+        let shift = |i: u32| match i {
+            NIL => NIL,
+            i => i + offset,
+        };
+        self.nodes.extend(other.nodes.into_iter().map(|mut node| {
+            node.left = shift(node.left);
+            node.right = shift(node.right);
+            node
+        }));
+        shift(other.root)
+    }
+
+    // Moves every node to the slot of its rank, so that the Vec is in key
+    // order. The tree keeps its shape.
+    // O(n)
+    fn sort_nodes(&mut self) {
+        let mut ranks = vec![0; self.nodes.len()];
+        self.rank_nodes(self.root, 0, &mut ranks);
+        let rank_of = |i: u32| match i {
+            NIL => NIL,
+            i => ranks[i as usize] as u32,
+        };
+        for node in self.nodes.iter_mut() {
+            node.left = rank_of(node.left);
+            node.right = rank_of(node.right);
+        }
+        self.root = rank_of(self.root);
+        // Follows each cycle of the permutation, putting one node in its
+        // final slot with every swap.
+        for i in 0..ranks.len() {
+            while ranks[i] != i {
+                let rank = ranks[i];
+                self.nodes.swap(i, rank);
+                ranks.swap(i, rank);
+            }
+        }
+    }
+
+    // Writes the rank of every node of the subtree at i into ranks, given
+    // the number of keys smaller than the whole subtree.
+    fn rank_nodes(&self, i: u32, smaller: usize, ranks: &mut [usize]) {
+        if i == NIL {
+            return;
+        }
+        let node = &self.nodes[i as usize];
+        let rank = smaller + self.size(node.left);
+        ranks[i as usize] = rank;
+        self.rank_nodes(node.left, smaller, ranks);
+        self.rank_nodes(node.right, rank + 1, ranks);
+    }
+
+    // Same as Node::build, but for nodes already stored in key order in
+    // [start, end). Links them into a perfectly balanced subtree, and
+    // returns the index of its root.
+    // O(end - start)
+    fn link_sorted(&mut self, start: usize, end: usize) -> u32 {
+        if start == end {
+            return NIL;
+        }
+        let middle = start + (end - start) / 2;
+        let left = self.link_sorted(start, middle);
+        let right = self.link_sorted(middle + 1, end);
+        let i = middle as u32;
+        self.nodes[middle].left = left;
+        self.nodes[middle].right = right;
+        self.update_counts(i);
+        i
+    }
+
+    // Builds a perfectly balanced tree from deduplicated, sorted entries.
+    fn from_sorted_vec(entries: Vec<(K, V)>) -> Self {
+        assert!(entries.len() < NIL as usize, "arena is full");
+        let mut tree = Self {
+            nodes: entries
+                .into_iter()
+                .map(|(key, value)| ArenaNode::new(key, value))
+                .collect(),
+            root: NIL,
+        };
+        tree.root = tree.link_sorted(0, tree.len());
+        tree
+    }
+
+    fn leftmost(&self) -> &ArenaNode<K, V> {
+        let mut current = &self.nodes[self.root as usize];
+        while current.left != NIL {
+            current = &self.nodes[current.left as usize];
+        }
+        current
+    }
+
+    fn rightmost(&self) -> &ArenaNode<K, V> {
+        let mut current = &self.nodes[self.root as usize];
+        while current.right != NIL {
+            current = &self.nodes[current.right as usize];
+        }
+        current
+    }
+
+    fn find<Q>(&self, key: &Q) -> Option<u32>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut current = self.root;
+        while current != NIL {
+            let node = &self.nodes[current as usize];
+            match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Some(current),
+                Ordering::Less => current = node.left,
+                Ordering::Greater => current = node.right,
+            }
+        }
+        None
+    }
+
+    // Index of the node with the i-th smallest key.
+    fn find_index(&self, mut i: usize) -> Option<u32> {
+        if i >= self.len() {
+            return None;
+        }
+        let mut current = self.root;
+        loop {
+            let node = &self.nodes[current as usize];
+            let left = self.size(node.left);
+            match i.cmp(&left) {
+                Ordering::Less => current = node.left,
+                Ordering::Equal => return Some(current),
+                Ordering::Greater => {
+                    i -= left + 1;
+                    current = node.right;
+                }
+            }
+        }
+    }
+
+    fn index_bounds<T: RangeBounds<K>>(&self, range: &T) -> (usize, usize) {
+        crate::index_bounds(range, self.len(), |key| self.rank(key))
+    }
+}
+
+impl<K, V> Default for ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    // Same as for RandomTree: sorts, then builds a perfectly balanced tree.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries = iter.into_iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        crate::dedup_sorted(&mut entries);
+        Self::from_sorted_vec(entries)
+    }
+}
+
+impl<K, V> Extend<(K, V)> for ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V> IntoIterator for ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    type Item = (K, V);
+    type IntoIter = ArenaIntoIter<K, V>;
+
+    // Iterates in key order.
+    // O(n) to set up, like iter_mut.
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.sort_nodes();
+        ArenaIntoIter {
+            inner: self.nodes.into_iter(),
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = ArenaIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut ArenaRandomTree<K, V>
+where
+    K: Ord,
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = ArenaIterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K, Q, V> Index<&Q> for ArenaRandomTree<K, V>
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+{
+    type Output = V;
+
+    // Panics if key is absent.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+// Same as Entry, for an ArenaRandomTree.
+pub enum ArenaEntry<'a, K, V>
+where
+    K: Ord,
+{
+    Vacant(ArenaVacantEntry<'a, K, V>),
+    Occupied(ArenaOccupiedEntry<'a, K, V>),
+}
+
+pub struct ArenaVacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    tree: &'a mut ArenaRandomTree<K, V>,
+    key: K,
+}
+
+// Holds the node's index, which stays put until the tree changes again.
+pub struct ArenaOccupiedEntry<'a, K, V> {
+    tree: &'a mut ArenaRandomTree<K, V>,
+    index: u32,
+}
+
+impl<'a, K, V> ArenaEntry<'a, K, V>
+where
+    K: Ord,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            ArenaEntry::Vacant(vacant) => vacant.key(),
+            ArenaEntry::Occupied(occupied) => occupied.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            ArenaEntry::Vacant(vacant) => vacant.insert(default()),
+            ArenaEntry::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let ArenaEntry::Occupied(occupied) = &mut self {
+            f(occupied.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V> ArenaVacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    // Rotations don't move nodes between slots, so the new node is found
+    // at the end of the Vec rather than by walking down again.
+    // O(log(n))
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.tree.insert(self.key, value);
+        &mut self.tree.nodes.last_mut().unwrap().value
+    }
+}
+
+impl<'a, K, V> ArenaOccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.tree.nodes[self.index as usize].key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.tree.nodes[self.index as usize].value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.tree.nodes[self.index as usize].value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.tree.nodes[self.index as usize].value
+    }
+
+    // Returns the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+}
+
+// A subtree of the arena, for the in-order iterator.
+struct ArenaRef<'a, K, V> {
+    nodes: &'a [ArenaNode<K, V>],
+    index: u32,
+}
+
+impl<'a, K, V> ArenaRef<'a, K, V> {
+    fn child(&self, index: u32) -> Option<Self> {
+        Some(Self {
+            nodes: self.nodes,
+            index,
+        })
+        .filter(|child| child.index != NIL)
+    }
+}
+
+impl<'a, K, V> Split for ArenaRef<'a, K, V> {
+    type Entry = (&'a K, &'a V);
+
+    fn size(&self) -> usize {
+        self.nodes[self.index as usize].size as usize
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let node = &self.nodes[self.index as usize];
+        (
+            self.child(node.left),
+            (&node.key, &node.value),
+            self.child(node.right),
+        )
+    }
+}
+
+pub struct ArenaIter<'a, K, V> {
+    inner: InOrder<ArenaRef<'a, K, V>>,
+}
+
+// Goes over the Vec itself, once iter_mut has sorted it.
+pub struct ArenaIterMut<'a, K, V> {
+    inner: std::slice::IterMut<'a, ArenaNode<K, V>>,
+}
+
+// Same as ArenaIterMut, by value.
+pub struct ArenaIntoIter<K, V> {
+    inner: std::vec::IntoIter<ArenaNode<K, V>>,
+}
+
+pub struct ArenaKeys<'a, K, V> {
+    inner: ArenaIter<'a, K, V>,
+}
+
+pub struct ArenaValues<'a, K, V> {
+    inner: ArenaIter<'a, K, V>,
+}
+
+pub struct ArenaValuesMut<'a, K, V> {
+    inner: ArenaIterMut<'a, K, V>,
+}
+
+// Implements the iterator traits for a type with an inner iterator, mapping
+// each item through $map.
+macro_rules! arena_iterator {
+    ($iter:ty, $item:ty, $map:expr, [$($generics:tt)*]) => {
+        impl<$($generics)*> Iterator for $iter {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.inner.next().map($map)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.inner.size_hint()
+            }
+        }
+
+        impl<$($generics)*> DoubleEndedIterator for $iter {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.inner.next_back().map($map)
+            }
+        }
+
+        impl<$($generics)*> ExactSizeIterator for $iter {
+        }
+
+        impl<$($generics)*> FusedIterator for $iter {
+        }
+    };
+}
+
+arena_iterator!(ArenaIter<'a, K, V>, (&'a K, &'a V), |entry| entry, ['a, K, V]);
+arena_iterator!(
+    ArenaIterMut<'a, K, V>,
+    (&'a K, &'a mut V),
+    |node: &'a mut ArenaNode<K, V>| (&node.key, &mut node.value),
+    ['a, K, V]
+);
+arena_iterator!(
+    ArenaIntoIter<K, V>,
+    (K, V),
+    |node: ArenaNode<K, V>| (node.key, node.value),
+    [K, V]
+);
+arena_iterator!(ArenaKeys<'a, K, V>, &'a K, |(key, _)| key, ['a, K, V]);
+arena_iterator!(ArenaValues<'a, K, V>, &'a V, |(_, value)| value, ['a, K, V]);
+arena_iterator!(ArenaValuesMut<'a, K, V>, &'a mut V, |(_, value)| value, ['a, K, V]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, SeedableRng};
+
+    impl<K, V> ArenaRandomTree<K, V>
+    where
+        K: Ord,
+    {
+        fn validate(&self) -> bool {
+            self.root == NIL && self.nodes.is_empty()
+                || self.validate_subtree(self.root) && self.size(self.root) == self.nodes.len()
+        }
+
+        fn validate_subtree(&self, i: u32) -> bool {
+            if i == NIL {
+                return true;
+            }
+            let node = &self.nodes[i as usize];
+            let (left, right) = (
+                self.balance_weight(node.left),
+                self.balance_weight(node.right),
+            );
+            self.size(i) == self.size(node.left) + self.size(node.right) + 1
+                && left <= DELTA * right
+                && right <= DELTA * left
+                && self.validate_subtree(node.left)
+                && self.validate_subtree(node.right)
+        }
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut rng = thread_rng();
+        let mut keys = (0..5_000).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+
+        let mut tree = ArenaRandomTree::new();
+        for k in keys.iter() {
+            assert_eq!(tree.insert(*k, k * 2), None);
+        }
+        assert_eq!(tree.insert(0, 1), Some(0));
+        assert!(tree.validate());
+        assert!(tree.keys().copied().eq(0..5_000));
+
+        keys.shuffle(&mut rng);
+        let (removed, kept) = keys.split_at(3_000);
+        for k in removed {
+            assert_eq!(tree.remove(k), Some(if *k == 0 { 1 } else { k * 2 }));
+            assert_eq!(tree.remove(k), None);
+        }
+        assert!(tree.validate());
+        assert_eq!(tree.len(), 2_000);
+        for k in kept {
+            assert_eq!(tree[k], if *k == 0 { 1 } else { k * 2 });
+        }
+    }
+
+    #[test]
+    fn sorted_insert_is_balanced() {
+        let mut tree = ArenaRandomTree::new();
+        for i in 0..100_000 {
+            tree.insert(i, ());
+        }
+        assert!(tree.validate());
+    }
+
+    #[test]
+    fn order_statistics() {
+        let mut tree = (0..1_000)
+            .rev()
+            .map(|k| (k * 10, ()))
+            .collect::<ArenaRandomTree<_, _>>();
+        assert!(tree.validate());
+        for i in 0..1_000 {
+            assert_eq!(tree.select(i), Some((&(i * 10), &())));
+            assert_eq!(tree.rank(&(i * 10)), Ok(i));
+            assert_eq!(tree.rank(&(i * 10 + 1)), Err(i + 1));
+        }
+        assert_eq!(tree.select(1_000), None);
+        assert!(tree
+            .iter()
+            .rev()
+            .map(|(k, _)| *k)
+            .eq((0..1_000).rev().map(|k| k * 10)));
+
+        assert_eq!(tree.get_index_mut(1_000), None);
+        assert_eq!(tree.get_index_mut(3), Some((&30, &mut ())));
+        assert_eq!(tree.remove_index(1_000), None);
+        for i in (0..1_000).step_by(2).rev() {
+            assert_eq!(tree.remove_index(i), Some((i * 10, ())));
+        }
+        assert!(tree.validate());
+        assert!(tree
+            .keys()
+            .copied()
+            .eq((0..1_000).skip(1).step_by(2).map(|k| k * 10)));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut rng = thread_rng();
+        let tree = (0..100)
+            .map(|k| (k * 2, ()))
+            .collect::<ArenaRandomTree<_, _>>();
+        assert_eq!(tree.count_range(10..20), 5);
+        assert_eq!(tree.count_range(11..=20), 5);
+        assert_eq!(tree.count_range(..), 100);
+        assert_eq!(tree.count_range(300..), 0);
+        assert!(tree.range(9..=14).map(|(k, _)| *k).eq(vec![10, 12, 14]));
+        assert!(tree
+            .range(15..)
+            .rev()
+            .map(|(k, _)| *k)
+            .eq((8..100).rev().map(|k| k * 2)));
+        assert!(tree.random_in_range(1..2, &mut rng).is_none());
+        for _ in 0..100 {
+            let (k, _) = tree.random_in_range(50..60, &mut rng).unwrap();
+            assert!((50..60).contains(k));
+        }
+
+        let sample = tree.sample_sorted(30, &mut rng);
+        assert_eq!(sample.len(), 30);
+        assert!(sample.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(tree.sample_sorted(300, &mut rng).len(), 100);
+    }
+
+    #[test]
+    fn entry() {
+        let mut counter = ArenaRandomTree::new();
+        for word in "the quick fox jumps over the lazy dog the end".split(' ') {
+            *counter.entry(word).or_insert(0) += 1;
+        }
+        assert_eq!(counter["the"], 3);
+        assert_eq!(counter["fox"], 1);
+
+        counter.entry("fox").and_modify(|n| *n *= 10).or_insert(0);
+        counter.entry("cat").and_modify(|n| *n *= 10).or_insert(7);
+        assert_eq!(counter["fox"], 10);
+        assert_eq!(counter["cat"], 7);
+        assert_eq!(*counter.entry("cow").or_default(), 0);
+
+        match counter.entry("dog") {
+            ArenaEntry::Occupied(mut occupied) => {
+                assert_eq!(occupied.key(), &"dog");
+                assert_eq!(occupied.insert(5), 1);
+                assert_eq!(occupied.get(), &5);
+            }
+            ArenaEntry::Vacant(_) => panic!("dog is there"),
+        }
+        match counter.entry("emu") {
+            ArenaEntry::Vacant(vacant) => assert_eq!(vacant.into_key(), "emu"),
+            ArenaEntry::Occupied(_) => panic!("emu isn't there"),
+        }
+        assert!(!counter.has("emu"));
+
+        let mut tree = ArenaRandomTree::new();
+        for i in 0..10_000 {
+            *tree.entry(i / 2).or_insert(0) += i;
+        }
+        assert!(tree.validate());
+        assert!(tree.iter().all(|(k, v)| *v == 4 * k + 1));
+    }
+
+    #[test]
+    fn mutable_and_owning_iterators() {
+        let mut rng = thread_rng();
+        let mut keys = (0..1_000).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+        let mut tree = ArenaRandomTree::new();
+        for k in keys.iter() {
+            tree.insert(*k, 0);
+        }
+        for k in keys[..300].iter() {
+            tree.remove(k);
+        }
+
+        for (k, v) in tree.iter_mut() {
+            *v = k * 3;
+        }
+        for v in tree.values_mut().rev().take(10) {
+            *v += 1;
+        }
+        assert!(tree.validate());
+        assert!(tree.keys().zip(tree.keys().skip(1)).all(|(a, b)| a < b));
+        let mut expected = keys[300..].to_vec();
+        expected.sort_unstable();
+        for (i, k) in expected.iter().enumerate() {
+            let bump = if i + 10 >= expected.len() { 1 } else { 0 };
+            assert_eq!(tree.get(k), Some(&(k * 3 + bump)));
+        }
+
+        tree.insert(1_000, 0);
+        assert!(tree
+            .into_iter()
+            .map(|(k, _)| k)
+            .eq(expected.into_iter().chain(Some(1_000))));
+    }
+
+    #[test]
+    fn split_append_join() {
+        let mut rng = thread_rng();
+        for len in 0..40 {
+            let mut keys = (0..len).map(|k| k * 2).collect::<Vec<_>>();
+            keys.shuffle(&mut rng);
+            for at in 0..=len * 2 + 1 {
+                // Inserted in random order, so that the slots are too.
+                let mut tree = ArenaRandomTree::new();
+                for k in keys.iter() {
+                    tree.insert(*k, *k);
+                }
+                let rest = tree.split_off(&at);
+                assert!(tree.validate() && rest.validate());
+                assert!(tree.iter().all(|(k, v)| k == v && *k < at));
+                assert!(rest.iter().all(|(k, v)| k == v && *k >= at));
+                assert_eq!(tree.len() + rest.len(), len);
+
+                let joined = ArenaRandomTree::join(tree, rest);
+                assert!(joined.validate());
+                assert!(joined.keys().copied().eq((0..len).map(|k| k * 2)));
+            }
+        }
+
+        let mut left = ArenaRandomTree::from_sorted_iter((0..500).map(|k| (k, 0)));
+        let mut right = (250..1_000).map(|k| (k, 1)).collect();
+        left.append(&mut right);
+        assert!(right.is_empty() && left.validate());
+        assert!(left.iter().all(|(k, v)| *v == if *k < 250 { 0 } else { 1 }));
+        let mut high = (2_000..2_100).map(|k| (k, 2)).collect();
+        left.append(&mut high);
+        let mut low = (-100..0).map(|k| (k, 3)).collect();
+        left.append(&mut low);
+        assert!(left.validate());
+        assert!(left.keys().copied().eq((-100..1_000).chain(2_000..2_100)));
+    }
+
+    #[test]
+    #[should_panic(expected = "key ranges overlap")]
+    fn join_overlapping() {
+        let left = ArenaRandomTree::from_sorted_iter(vec![(1, ()), (5, ())]);
+        let right = ArenaRandomTree::from_sorted_iter(vec![(3, ())]);
+        ArenaRandomTree::join(left, right);
+    }
+
+    #[test]
+    fn matches_boxed_draws() {
+        let boxed = (0..1_000)
+            .map(|k| (k, ()))
+            .collect::<crate::RandomTree<_, _>>();
+        let arena = (0..1_000)
+            .map(|k| (k, ()))
+            .collect::<ArenaRandomTree<_, _>>();
+
+        let mut boxed_rng = StdRng::seed_from_u64(7);
+        let mut arena_rng = StdRng::seed_from_u64(7);
+        for _ in 0..1_000 {
+            assert_eq!(boxed.random(&mut boxed_rng), arena.random(&mut arena_rng));
+        }
+
+        let mut rng = thread_rng();
+        let mut sample = arena
+            .sample(100, &mut rng)
+            .into_iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        sample.sort_unstable();
+        sample.dedup();
+        assert_eq!(sample.len(), 100);
+        assert!(ArenaRandomTree::<u8, ()>::new().random(&mut rng).is_none());
+    }
+}
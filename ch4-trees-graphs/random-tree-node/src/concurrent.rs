@@ -0,0 +1,226 @@
+use crate::RandomTree;
+use rand::Rng;
+use std::{
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+};
+
+struct Shard<K, V> {
+    tree: RwLock<RandomTree<K, V>>,
+    // Same as tree.len(), but readable without the lock. Only changes while
+    // the write lock is held.
+    len: AtomicUsize,
+}
+
+// A RandomTree that can be shared between threads, and read and written
+// from all of them at once.
+// The keys are spread over shards by hash, each a RandomTree behind its own
+// RwLock, so writers only block the readers and writers of one shard.
+// random picks a shard in proportion to its size, then an entry inside it,
+// which is uniform overall whenever no writer is halfway through.
+pub struct ConcurrentRandomTree<K, V> {
+    shards: Vec<Shard<K, V>>,
+    hasher: RandomState,
+}
+
+impl<K, V> ConcurrentRandomTree<K, V>
+where
+    K: Ord + Hash,
+{
+    // Uses one shard per available thread, times 4.
+    pub fn new() -> Self {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        Self::with_shards(threads * 4)
+    }
+
+    // Panics if shards is 0.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "no shards");
+        Self {
+            shards: (0..shards)
+                .map(|_| Shard {
+                    tree: RwLock::new(RandomTree::new()),
+                    len: AtomicUsize::new(0),
+                })
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    // O(shards)
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.len.load(Ordering::Acquire))
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Returns the old value associated with K, or None if the key is new.
+    // O(log(n)), plus waiting for the shard's lock.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let shard = self.shard(&key);
+        let mut tree = shard.tree.write().unwrap();
+        let old = tree.insert(key, value);
+        shard.len.store(tree.len(), Ordering::Release);
+        old
+    }
+
+    // Returns the value associated with K, or None if the key was absent.
+    // O(log(n)), plus waiting for the shard's lock.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + Hash + ?Sized,
+    {
+        let shard = self.shard(key);
+        let mut tree = shard.tree.write().unwrap();
+        let old = tree.remove(key);
+        shard.len.store(tree.len(), Ordering::Release);
+        old
+    }
+
+    // Returns a copy, since the shard is unlocked again on return.
+    // O(log(n))
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + Hash + ?Sized,
+        V: Clone,
+    {
+        self.shard(key).tree.read().unwrap().get(key).cloned()
+    }
+
+    // O(log(n))
+    pub fn has<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + Hash + ?Sized,
+    {
+        self.shard(key).tree.read().unwrap().has(key)
+    }
+
+    // Returns a copy of a uniformly random entry, or None iff the tree is
+    // empty.
+    // O(shards + log(n)). Only locks the shard it picks.
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        loop {
+            let total = self.len();
+            if total == 0 {
+                return None;
+            }
+            let mut i = rng.gen_range(0, total);
+            for shard in self.shards.iter() {
+                let len = shard.len.load(Ordering::Acquire);
+                if i < len {
+                    let tree = shard.tree.read().unwrap();
+                    // A writer may have emptied the shard in the meantime.
+                    if let Some((key, value)) = tree.random(rng) {
+                        return Some((key.clone(), value.clone()));
+                    }
+                    break;
+                }
+                i -= len;
+            }
+            // The sizes changed under us, so draw again.
+        }
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Shard<K, V> {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+}
+
+impl<K, V> Default for ConcurrentRandomTree<K, V>
+where
+    K: Ord + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::{collections::HashSet, sync::Arc, thread};
+
+    #[test]
+    fn sampling_across_shards() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let tree = ConcurrentRandomTree::with_shards(7);
+        assert!(tree.random(&mut rng).is_none());
+        for k in 0..4 {
+            assert_eq!(tree.insert(k, k * 2), None);
+        }
+        assert_eq!(tree.insert(3, 0), Some(6));
+        assert_eq!(tree.get(&3), Some(0));
+        assert_eq!(tree.remove(&3), Some(0));
+        assert!(!tree.has(&3));
+        assert_eq!(tree.len(), 3);
+
+        let mut counts = [0; 3];
+        for _ in 0..9_000 {
+            let (k, v) = tree.random(&mut rng).unwrap();
+            assert_eq!(v, k * 2);
+            counts[k] += 1;
+        }
+        assert!(counts.iter().all(|count| (2_700..3_300).contains(count)));
+    }
+
+    #[test]
+    fn stress() {
+        const THREADS: u64 = 8;
+        const KEYS: u64 = 1_000;
+
+        let tree = Arc::new(ConcurrentRandomTree::with_shards(4));
+        let writers = (0..THREADS)
+            .map(|t| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    // Each thread owns its own range of keys, so it knows
+                    // exactly which of them are in the tree.
+                    let mut rng = StdRng::seed_from_u64(t);
+                    let mut mine = HashSet::new();
+                    for _ in 0..20_000 {
+                        let key = t * KEYS + rng.gen_range(0, KEYS);
+                        match rng.gen_range(0, 3) {
+                            0 => assert_eq!(tree.remove(&key).is_some(), mine.remove(&key)),
+                            1 => assert_eq!(tree.insert(key, t).is_none(), mine.insert(key)),
+                            _ => {
+                                let (key, owner) = tree.random(&mut rng).unwrap_or((0, 0));
+                                assert_eq!(key / KEYS, owner);
+                            }
+                        }
+                    }
+                    mine.len()
+                })
+            })
+            .collect::<Vec<_>>();
+        let expected = writers
+            .into_iter()
+            .map(|writer| writer.join().unwrap())
+            .sum::<usize>();
+
+        assert_eq!(tree.len(), expected);
+        for shard in tree.shards.iter() {
+            let shard_tree = shard.tree.read().unwrap();
+            assert_eq!(shard.len.load(Ordering::Acquire), shard_tree.len());
+            assert_eq!(shard_tree.iter().count(), shard_tree.len());
+        }
+    }
+}
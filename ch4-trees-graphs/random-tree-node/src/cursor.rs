@@ -0,0 +1,463 @@
+use crate::{Node, RandomTree, Summary};
+use std::cmp::Ordering;
+
+// A position in a RandomTree, from RandomTree::cursor_at and friends.
+// Either on an entry, or on the "ghost" position past the last entry, which
+// sits between the last and the first entry when moving around.
+// Moving to a neighbour is O(1) amortized, since the cursor keeps the path
+// from the root.
+pub struct Cursor<'a, K, V, S = ()> {
+    root: Option<&'a Node<K, V, S>>,
+    // The path from the root to the current node. Empty on the ghost.
+    stack: Vec<&'a Node<K, V, S>>,
+    index: usize,
+    len: usize,
+}
+
+impl<'a, K, V, S> Cursor<'a, K, V, S>
+where
+    S: Summary<K, V>,
+{
+    pub(crate) fn new(tree: &'a RandomTree<K, V, S>, index: usize) -> Self {
+        let root = tree.root.as_deref();
+        let mut cursor = Self {
+            root,
+            stack: Vec::new(),
+            index: 0,
+            len: root.map_or(0, |root| root.size()),
+        };
+        cursor.descend_to(index);
+        cursor
+    }
+
+    pub fn key(&self) -> Option<&'a K> {
+        self.stack.last().map(|node| &node.key)
+    }
+
+    pub fn value(&self) -> Option<&'a V> {
+        self.stack.last().map(|node| &node.value)
+    }
+
+    // Position of the current entry in key order, or None on the ghost.
+    pub fn index(&self) -> Option<usize> {
+        self.stack.last().map(|_| self.index)
+    }
+
+    // Moves to the next entry. From the last entry, moves to the ghost, and
+    // from the ghost, to the first entry.
+    // O(1) amortized.
+    pub fn move_next(&mut self) {
+        let node = match self.stack.last() {
+            None => return self.descend_to(0),
+            Some(node) => *node,
+        };
+        if let Some(mut next) = node.right.as_deref() {
+            self.stack.push(next);
+            while let Some(left) = next.left.as_deref() {
+                self.stack.push(left);
+                next = left;
+            }
+            self.index += 1;
+            return;
+        }
+        // Up until we leave a left subtree.
+        while let Some(child) = self.stack.pop() {
+            if let Some(parent) = self.stack.last() {
+                if parent
+                    .left
+                    .as_deref()
+                    .is_some_and(|left| std::ptr::eq(left, child))
+                {
+                    self.index += 1;
+                    return;
+                }
+            }
+        }
+        self.index = self.len;
+    }
+
+    // Same as move_next, in the other direction.
+    // O(1) amortized.
+    pub fn move_prev(&mut self) {
+        let node = match self.stack.last() {
+            None => return self.descend_to(self.len.wrapping_sub(1)),
+            Some(node) => *node,
+        };
+        if let Some(mut prev) = node.left.as_deref() {
+            self.stack.push(prev);
+            while let Some(right) = prev.right.as_deref() {
+                self.stack.push(right);
+                prev = right;
+            }
+            self.index -= 1;
+            return;
+        }
+        while let Some(child) = self.stack.pop() {
+            if let Some(parent) = self.stack.last() {
+                if parent
+                    .right
+                    .as_deref()
+                    .is_some_and(|right| std::ptr::eq(right, child))
+                {
+                    self.index -= 1;
+                    return;
+                }
+            }
+        }
+        self.index = self.len;
+    }
+
+    // Moves to the entry at index, or to the ghost if there is none.
+    // O(log(n))
+    fn descend_to(&mut self, mut i: usize) {
+        self.stack.clear();
+        self.index = i.min(self.len);
+        if i >= self.len {
+            return;
+        }
+        let mut current = self.root.unwrap();
+        loop {
+            self.stack.push(current);
+            let left = current.left_children();
+            match i.cmp(&left) {
+                Ordering::Less => current = current.left.as_deref().unwrap(),
+                Ordering::Equal => return,
+                Ordering::Greater => {
+                    i -= left + 1;
+                    current = current.right.as_deref().unwrap();
+                }
+            }
+        }
+    }
+}
+
+// The nodes above the current one, and which of their children leads to it.
+type Ancestors<K, V, S> = Vec<(Box<Node<K, V, S>>, Ordering)>;
+
+// Same as Cursor, but can change the tree.
+// While it is open, the nodes on the path from the root to the current one
+// are taken out of the tree, each missing the child the path goes through.
+// Their counts and summaries are fixed when they are put back together, on
+// the way up or on Drop.
+// Moving and changing the current value are O(1) amortized, inserting and
+// removing are O(log(n)).
+pub struct CursorMut<'a, K, V, S = ()>
+where
+    K: Ord,
+    S: Summary<K, V>,
+{
+    tree: &'a mut RandomTree<K, V, S>,
+    ancestors: Ancestors<K, V, S>,
+    // None on the ghost, in which case the tree is whole.
+    current: Option<Box<Node<K, V, S>>>,
+    index: usize,
+    len: usize,
+}
+
+impl<'a, K, V, S> CursorMut<'a, K, V, S>
+where
+    K: Ord,
+    S: Summary<K, V>,
+{
+    pub(crate) fn new(tree: &'a mut RandomTree<K, V, S>, index: usize) -> Self {
+        let len = tree.len();
+        let mut cursor = Self {
+            tree,
+            ancestors: Vec::new(),
+            current: None,
+            index: 0,
+            len,
+        };
+        cursor.descend_to(index);
+        cursor
+    }
+
+    pub fn key(&self) -> Option<&K> {
+        self.current.as_ref().map(|node| &node.key)
+    }
+
+    pub fn value(&self) -> Option<&V> {
+        self.current.as_ref().map(|node| &node.value)
+    }
+
+    // The summaries above are brought up to date once the cursor moves off.
+    pub fn value_mut(&mut self) -> Option<&mut V> {
+        self.current.as_mut().map(|node| &mut node.value)
+    }
+
+    pub fn index(&self) -> Option<usize> {
+        self.current.as_ref().map(|_| self.index)
+    }
+
+    // Same as Cursor::move_next.
+    // O(1) amortized.
+    pub fn move_next(&mut self) {
+        let mut node = match self.current.take() {
+            None => return self.descend_to(0),
+            Some(node) => node,
+        };
+        if let Some(mut next) = node.right.take() {
+            self.ancestors.push((node, Ordering::Greater));
+            while let Some(left) = next.left.take() {
+                self.ancestors.push((next, Ordering::Less));
+                next = left;
+            }
+            self.current = Some(next);
+            self.index += 1;
+            return;
+        }
+        // Up until we leave a left subtree.
+        node.update_counts();
+        while let Some((mut parent, direction)) = self.ancestors.pop() {
+            match direction {
+                Ordering::Less => parent.left = Some(node),
+                _ => parent.right = Some(node),
+            }
+            parent.update_counts();
+            if direction == Ordering::Less {
+                self.current = Some(parent);
+                self.index += 1;
+                return;
+            }
+            node = parent;
+        }
+        self.tree.root = Some(node);
+        self.index = self.len;
+    }
+
+    // Same as Cursor::move_prev.
+    // O(1) amortized.
+    pub fn move_prev(&mut self) {
+        let mut node = match self.current.take() {
+            None => return self.descend_to(self.len.wrapping_sub(1)),
+            Some(node) => node,
+        };
+        if let Some(mut prev) = node.left.take() {
+            self.ancestors.push((node, Ordering::Less));
+            while let Some(right) = prev.right.take() {
+                self.ancestors.push((prev, Ordering::Greater));
+                prev = right;
+            }
+            self.current = Some(prev);
+            self.index -= 1;
+            return;
+        }
+        node.update_counts();
+        while let Some((mut parent, direction)) = self.ancestors.pop() {
+            match direction {
+                Ordering::Less => parent.left = Some(node),
+                _ => parent.right = Some(node),
+            }
+            parent.update_counts();
+            if direction == Ordering::Greater {
+                self.current = Some(parent);
+                self.index -= 1;
+                return;
+            }
+            node = parent;
+        }
+        self.tree.root = Some(node);
+        self.index = self.len;
+    }
+
+    // Inserts an entry right before the current one, or at the end on the
+    // ghost. The cursor stays on the current entry.
+    // Panics unless key falls between the neighbouring keys.
+    // O(log(n))
+    pub fn insert_before(&mut self, key: K, value: V) {
+        self.insert_at(self.index, key, value);
+    }
+
+    // Inserts an entry right after the current one, or at the start on the
+    // ghost. The cursor stays on the current entry.
+    // Panics unless key falls between the neighbouring keys.
+    // O(log(n))
+    pub fn insert_after(&mut self, key: K, value: V) {
+        let rank = if self.current.is_some() {
+            self.index + 1
+        } else {
+            0
+        };
+        self.insert_at(rank, key, value);
+    }
+
+    // Removes the current entry, and moves to the next one.
+    // Returns None on the ghost.
+    // O(log(n))
+    pub fn remove_current(&mut self) -> Option<(K, V)> {
+        self.current.as_ref()?;
+        self.zip_up();
+        let removed = self.tree.remove_index(self.index);
+        self.len -= 1;
+        self.descend_to(self.index);
+        removed
+    }
+
+    fn insert_at(&mut self, rank: usize, key: K, value: V) {
+        let on_ghost = self.current.is_none();
+        self.zip_up();
+        let after_previous = rank == 0 || self.tree.select(rank - 1).unwrap().0 < &key;
+        let before_next = self.tree.select(rank).is_none_or(|(next, _)| &key < next);
+        assert!(after_previous && before_next, "key out of order");
+        self.tree.insert(key, value);
+        self.len += 1;
+        let index = if on_ghost {
+            self.len
+        } else if rank <= self.index {
+            self.index + 1
+        } else {
+            self.index
+        };
+        self.descend_to(index);
+    }
+
+    // Puts the tree back together. Leaves the cursor on the ghost, with
+    // index unchanged.
+    fn zip_up(&mut self) {
+        if let Some(mut node) = self.current.take() {
+            node.update_counts();
+            while let Some((mut parent, direction)) = self.ancestors.pop() {
+                match direction {
+                    Ordering::Less => parent.left = Some(node),
+                    _ => parent.right = Some(node),
+                }
+                parent.update_counts();
+                node = parent;
+            }
+            self.tree.root = Some(node);
+        }
+    }
+
+    // Takes the path to the entry at index out of the whole tree, or moves
+    // to the ghost if there is none.
+    // O(log(n))
+    fn descend_to(&mut self, mut i: usize) {
+        self.index = i.min(self.len);
+        if i >= self.len {
+            return;
+        }
+        let mut node = self.tree.root.take().unwrap();
+        loop {
+            let left = node.left_children();
+            match i.cmp(&left) {
+                Ordering::Less => {
+                    let child = node.left.take().unwrap();
+                    self.ancestors.push((node, Ordering::Less));
+                    node = child;
+                }
+                Ordering::Equal => break,
+                Ordering::Greater => {
+                    i -= left + 1;
+                    let child = node.right.take().unwrap();
+                    self.ancestors.push((node, Ordering::Greater));
+                    node = child;
+                }
+            }
+        }
+        self.current = Some(node);
+    }
+}
+
+impl<'a, K, V, S> Drop for CursorMut<'a, K, V, S>
+where
+    K: Ord,
+    S: Summary<K, V>,
+{
+    fn drop(&mut self) {
+        self.zip_up();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RandomTree, Sum};
+
+    #[test]
+    fn walk() {
+        let tree = (0..100).map(|k| (k * 2, k)).collect::<RandomTree<_, _>>();
+        let mut cursor = tree.cursor_at(&51);
+        assert_eq!(cursor.key(), Some(&52));
+        assert_eq!(cursor.index(), Some(26));
+        for k in 27..100 {
+            cursor.move_next();
+            assert_eq!(cursor.value(), Some(&k));
+            assert_eq!(cursor.index(), Some(k));
+        }
+        cursor.move_next();
+        assert_eq!(cursor.key(), None);
+        assert_eq!(cursor.index(), None);
+        cursor.move_next();
+        assert_eq!(cursor.key(), Some(&0));
+        cursor.move_prev();
+        assert_eq!(cursor.key(), None);
+        for k in (0..100).rev() {
+            cursor.move_prev();
+            assert_eq!(cursor.key(), Some(&(k * 2)));
+            assert_eq!(cursor.index(), Some(k));
+        }
+
+        assert_eq!(tree.cursor_at(&1_000).key(), None);
+        assert_eq!(tree.cursor_at_index(10).key(), Some(&20));
+        let empty = RandomTree::<u8, ()>::new();
+        let mut cursor = empty.cursor_at_index(0);
+        cursor.move_next();
+        cursor.move_prev();
+        assert_eq!(cursor.key(), None);
+    }
+
+    #[test]
+    fn edit() {
+        let mut tree = (0..100)
+            .map(|k| (k * 10, k))
+            .collect::<RandomTree<i32, i32, Sum<i32>>>();
+        {
+            let mut cursor = tree.cursor_mut_at(&500);
+            for _ in 0..10 {
+                *cursor.value_mut().unwrap() = 0;
+                cursor.move_next();
+            }
+            cursor.move_prev();
+            assert_eq!(cursor.key(), Some(&590));
+            cursor.insert_before(585, 1_000);
+            cursor.insert_after(595, 1_000);
+            assert_eq!(cursor.key(), Some(&590));
+            assert_eq!(cursor.index(), Some(60));
+            cursor.move_prev();
+            assert_eq!(cursor.key(), Some(&585));
+            assert_eq!(cursor.remove_current(), Some((585, 1_000)));
+            assert_eq!(cursor.key(), Some(&590));
+            cursor.move_next();
+            assert_eq!(cursor.key(), Some(&595));
+        }
+        assert_eq!(tree.len(), 101);
+        assert_eq!(tree.count_range(590..=600), 3);
+        let expected = (0..100).sum::<i32>() - (50..60).sum::<i32>() + 1_000;
+        assert_eq!(tree.summary(), Sum(expected));
+        assert_eq!(tree.summarize(500..600), Sum(1_000));
+
+        // Removing everything, then inserting from the ghost.
+        {
+            let mut cursor = tree.cursor_mut_at_index(0);
+            while cursor.remove_current().is_some() {}
+            assert_eq!(cursor.index(), None);
+            cursor.insert_before(1, 1);
+            cursor.insert_before(3, 3);
+            cursor.insert_after(0, 0);
+            cursor.move_prev();
+            cursor.insert_before(2, 2);
+            assert_eq!(cursor.key(), Some(&3));
+        }
+        assert!(tree
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..4).map(|k| (k, k))));
+        assert_eq!(tree.summary(), Sum(6));
+    }
+
+    #[test]
+    #[should_panic(expected = "key out of order")]
+    fn insert_out_of_order() {
+        let mut tree = (0..10).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+        tree.cursor_mut_at(&5).insert_after(7, ());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{Node, RandomTree};
+
+// A view into a single entry of a RandomTree, from RandomTree::entry.
+pub enum Entry<'a, K, V>
+where
+    K: Ord,
+{
+    Vacant(VacantEntry<'a, K, V>),
+    Occupied(OccupiedEntry<'a, K, V>),
+}
+
+pub struct VacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    tree: &'a mut RandomTree<K, V>,
+    key: K,
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    node: &'a mut Node<K, V>,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Ord,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(vacant) => vacant.key(),
+            Entry::Occupied(occupied) => occupied.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(vacant) => vacant.insert(default()),
+            Entry::Occupied(occupied) => occupied.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(occupied) = &mut self {
+            f(occupied.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Ord,
+{
+    pub(crate) fn new(tree: &'a mut RandomTree<K, V>, key: K) -> Self {
+        Self { tree, key }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    // O(log(n))
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.tree.insert_new(self.key, value)
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub(crate) fn new(node: &'a mut Node<K, V>) -> Self {
+        Self { node }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.node.key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.node.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.node.value
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.node.value
+    }
+
+    // Returns the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(&mut self.node.value, value)
+    }
+}
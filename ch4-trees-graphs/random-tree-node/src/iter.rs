@@ -0,0 +1,433 @@
+use crate::{Node, Summary};
+use rand::Rng;
+use std::{
+    collections::{HashMap, VecDeque},
+    iter::FusedIterator,
+};
+
+// The in-order iterators all work the same way: a deque of pieces in key
+// order, each either a whole subtree or a single entry. Taking from either
+// end splits subtrees until an entry comes out, so each end only ever
+// holds O(log(n)) pieces, and skipping whole subtrees is cheap.
+pub(crate) trait Split: Sized {
+    type Entry;
+
+    fn size(&self) -> usize;
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>);
+}
+
+impl<'a, K, V, S> Split for &'a Node<K, V, S> {
+    type Entry = (&'a K, &'a V);
+
+    fn size(&self) -> usize {
+        Node::size(self)
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        (
+            self.left.as_deref(),
+            (&self.key, &self.value),
+            self.right.as_deref(),
+        )
+    }
+}
+
+impl<'a, K, V, S> Split for &'a mut Node<K, V, S> {
+    type Entry = (&'a K, &'a mut V);
+
+    fn size(&self) -> usize {
+        Node::size(self)
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let Node {
+            key,
+            value,
+            left,
+            right,
+            ..
+        } = self;
+        (left.as_deref_mut(), (&*key, value), right.as_deref_mut())
+    }
+}
+
+impl<K, V, S> Split for Box<Node<K, V, S>> {
+    type Entry = (K, V);
+
+    fn size(&self) -> usize {
+        Node::size(self)
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        let node = *self;
+        (node.left, (node.key, node.value), node.right)
+    }
+}
+
+enum Piece<S: Split> {
+    Subtree(S),
+    Entry(S::Entry),
+}
+
+pub(crate) struct InOrder<S: Split> {
+    pieces: VecDeque<Piece<S>>,
+    len: usize,
+}
+
+impl<S: Split> InOrder<S> {
+    pub(crate) fn new(root: Option<S>) -> Self {
+        let len = root.as_ref().map_or(0, |root| root.size());
+        let pieces = root.map(Piece::Subtree).into_iter().collect();
+        Self { pieces, len }
+    }
+
+    // Only yields the entries with indices in [start, end).
+    pub(crate) fn range(root: Option<S>, start: usize, end: usize) -> Self {
+        let mut in_order = Self::new(root);
+        let end = end.clamp(start, in_order.len);
+        in_order.skip_back(in_order.len - end);
+        in_order.skip_front(start);
+        in_order
+    }
+
+    pub(crate) fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+
+    pub(crate) fn next(&mut self) -> Option<S::Entry> {
+        while let Some(piece) = self.pieces.pop_front() {
+            match piece {
+                Piece::Entry(entry) => {
+                    self.len -= 1;
+                    return Some(entry);
+                }
+                Piece::Subtree(subtree) => {
+                    let (left, entry, right) = subtree.split();
+                    if let Some(right) = right {
+                        self.pieces.push_front(Piece::Subtree(right));
+                    }
+                    self.pieces.push_front(Piece::Entry(entry));
+                    if let Some(left) = left {
+                        self.pieces.push_front(Piece::Subtree(left));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub(crate) fn next_back(&mut self) -> Option<S::Entry> {
+        while let Some(piece) = self.pieces.pop_back() {
+            match piece {
+                Piece::Entry(entry) => {
+                    self.len -= 1;
+                    return Some(entry);
+                }
+                Piece::Subtree(subtree) => {
+                    let (left, entry, right) = subtree.split();
+                    if let Some(left) = left {
+                        self.pieces.push_back(Piece::Subtree(left));
+                    }
+                    self.pieces.push_back(Piece::Entry(entry));
+                    if let Some(right) = right {
+                        self.pieces.push_back(Piece::Subtree(right));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Drops the first n entries.
+    // O(log(len)), since whole subtrees are skipped at once.
+    fn skip_front(&mut self, mut n: usize) {
+        while n > 0 {
+            match self.pieces.pop_front() {
+                None => return,
+                Some(Piece::Subtree(subtree)) if subtree.size() > n => {
+                    let (left, entry, right) = subtree.split();
+                    if let Some(right) = right {
+                        self.pieces.push_front(Piece::Subtree(right));
+                    }
+                    self.pieces.push_front(Piece::Entry(entry));
+                    if let Some(left) = left {
+                        self.pieces.push_front(Piece::Subtree(left));
+                    }
+                }
+                Some(Piece::Subtree(subtree)) => {
+                    n -= subtree.size();
+                    self.len -= subtree.size();
+                }
+                Some(Piece::Entry(_)) => {
+                    n -= 1;
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+
+    // Same as skip_front, from the other end.
+    fn skip_back(&mut self, mut n: usize) {
+        while n > 0 {
+            match self.pieces.pop_back() {
+                None => return,
+                Some(Piece::Subtree(subtree)) if subtree.size() > n => {
+                    let (left, entry, right) = subtree.split();
+                    if let Some(left) = left {
+                        self.pieces.push_back(Piece::Subtree(left));
+                    }
+                    self.pieces.push_back(Piece::Entry(entry));
+                    if let Some(right) = right {
+                        self.pieces.push_back(Piece::Subtree(right));
+                    }
+                }
+                Some(Piece::Subtree(subtree)) => {
+                    n -= subtree.size();
+                    self.len -= subtree.size();
+                }
+                Some(Piece::Entry(_)) => {
+                    n -= 1;
+                    self.len -= 1;
+                }
+            }
+        }
+    }
+}
+
+// In-order iterators.
+pub struct Iter<'a, K, V, S = ()> {
+    inner: InOrder<&'a Node<K, V, S>>,
+}
+
+pub struct IterMut<'a, K, V> {
+    inner: InOrder<&'a mut Node<K, V>>,
+}
+
+pub struct IntoIter<K, V, S = ()> {
+    inner: InOrder<Box<Node<K, V, S>>>,
+}
+
+pub struct Keys<'a, K, V, S = ()> {
+    inner: Iter<'a, K, V, S>,
+}
+
+pub struct Values<'a, K, V, S = ()> {
+    inner: Iter<'a, K, V, S>,
+}
+
+pub struct ValuesMut<'a, K, V> {
+    inner: IterMut<'a, K, V>,
+}
+
+impl<'a, K, V, S> Iter<'a, K, V, S> {
+    pub(crate) fn new(root: Option<&'a Node<K, V, S>>) -> Self {
+        Self {
+            inner: InOrder::new(root),
+        }
+    }
+
+    pub(crate) fn range(root: Option<&'a Node<K, V, S>>, start: usize, end: usize) -> Self {
+        Self {
+            inner: InOrder::range(root, start, end),
+        }
+    }
+}
+
+impl<'a, K, V> IterMut<'a, K, V> {
+    pub(crate) fn new(root: Option<&'a mut Node<K, V>>) -> Self {
+        Self {
+            inner: InOrder::new(root),
+        }
+    }
+}
+
+impl<K, V, S> IntoIter<K, V, S> {
+    pub(crate) fn new(root: Option<Box<Node<K, V, S>>>) -> Self {
+        Self {
+            inner: InOrder::new(root),
+        }
+    }
+}
+
+impl<'a, K, V, S> Keys<'a, K, V, S> {
+    pub(crate) fn new(inner: Iter<'a, K, V, S>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, K, V, S> Values<'a, K, V, S> {
+    pub(crate) fn new(inner: Iter<'a, K, V, S>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, K, V> ValuesMut<'a, K, V> {
+    pub(crate) fn new(inner: IterMut<'a, K, V>) -> Self {
+        Self { inner }
+    }
+}
+
+// Implements the iterator traits for a type with an InOrder field, mapping
+// each entry through $map.
+macro_rules! in_order_iterator {
+    ($iter:ty, $item:ty, $map:expr, [$($generics:tt)*]) => {
+        impl<$($generics)*> Iterator for $iter {
+            type Item = $item;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                self.inner.next().map($map)
+            }
+
+            fn size_hint(&self) -> (usize, Option<usize>) {
+                self.inner.size_hint()
+            }
+        }
+
+        impl<$($generics)*> DoubleEndedIterator for $iter {
+            fn next_back(&mut self) -> Option<Self::Item> {
+                self.inner.next_back().map($map)
+            }
+        }
+
+        impl<$($generics)*> ExactSizeIterator for $iter {
+        }
+
+        impl<$($generics)*> FusedIterator for $iter {
+        }
+    };
+}
+
+in_order_iterator!(Iter<'a, K, V, S>, (&'a K, &'a V), |entry| entry, ['a, K, V, S]);
+in_order_iterator!(IterMut<'a, K, V>, (&'a K, &'a mut V), |entry| entry, ['a, K, V]);
+in_order_iterator!(IntoIter<K, V, S>, (K, V), |entry| entry, [K, V, S]);
+in_order_iterator!(Keys<'a, K, V, S>, &'a K, |(key, _)| key, ['a, K, V, S]);
+in_order_iterator!(Values<'a, K, V, S>, &'a V, |(_, value)| value, ['a, K, V, S]);
+in_order_iterator!(ValuesMut<'a, K, V>, &'a mut V, |(_, value)| value, ['a, K, V]);
+
+// Level-order iterators: the root first, then each level of the tree from
+// left to right. Every parent comes before its children, so inserting the
+// entries into an empty tree in this order rebuilds about the same shape,
+// with few rotations. They need no counts, unlike the in-order ones.
+// For a uniformly random order, use RandomTree::shuffled.
+pub struct IterBfs<'a, K, V, S = ()> {
+    queue: VecDeque<&'a Node<K, V, S>>,
+}
+
+pub struct IntoIterBfs<K, V, S = ()> {
+    queue: VecDeque<Node<K, V, S>>,
+}
+
+impl<'a, K, V, S> IterBfs<'a, K, V, S> {
+    pub(crate) fn new(root: Option<&'a Node<K, V, S>>) -> Self {
+        Self {
+            queue: root.into_iter().collect(),
+        }
+    }
+}
+
+impl<K, V, S> IntoIterBfs<K, V, S> {
+    pub(crate) fn new(root: Option<Box<Node<K, V, S>>>) -> Self {
+        Self {
+            queue: root.map(|boxed_root| *boxed_root).into_iter().collect(),
+        }
+    }
+}
+
+impl<'a, K, V, S> Iterator for IterBfs<'a, K, V, S> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.pop_front().map(|current_node| {
+            if let Some(left) = current_node.left.as_ref() {
+                self.queue.push_back(left);
+            }
+            if let Some(right) = current_node.right.as_ref() {
+                self.queue.push_back(right);
+            }
+            (&current_node.key, &current_node.value)
+        })
+    }
+}
+
+impl<K, V, S> Iterator for IntoIterBfs<K, V, S> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.pop_front().map(|mut current_node| {
+            if let Some(boxed_left) = current_node.left.take() {
+                self.queue.push_back(*boxed_left);
+            }
+            if let Some(boxed_right) = current_node.right.take() {
+                self.queue.push_back(*boxed_right);
+            }
+            (current_node.key, current_node.value)
+        })
+    }
+}
+
+// Yields every entry once, in uniformly random order.
+// A Fisher-Yates shuffle of the ranks, drawn one at a time and looked up
+// with select. Only the ranks that were swapped are stored, so taking k
+// entries is O(k * log(n)) time and O(k) memory.
+pub struct Shuffled<'a, K, V, S, R> {
+    root: Option<&'a Node<K, V, S>>,
+    // Ranks 0..remaining haven't been yielded yet. A rank missing from
+    // swapped stands for itself.
+    remaining: usize,
+    swapped: HashMap<usize, usize>,
+    rng: R,
+}
+
+impl<'a, K, V, S, R> Shuffled<'a, K, V, S, R> {
+    pub(crate) fn new(root: Option<&'a Node<K, V, S>>, rng: R) -> Self {
+        Self {
+            root,
+            remaining: root.map_or(0, |root| root.size()),
+            swapped: HashMap::new(),
+            rng,
+        }
+    }
+}
+
+impl<'a, K, V, S, R> Iterator for Shuffled<'a, K, V, S, R>
+where
+    S: Summary<K, V>,
+    R: Rng,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let i = self.rng.gen_range(0, self.remaining);
+        self.remaining -= 1;
+        let last = self
+            .swapped
+            .remove(&self.remaining)
+            .unwrap_or(self.remaining);
+        let rank = if i == self.remaining {
+            last
+        } else {
+            self.swapped.insert(i, last).unwrap_or(i)
+        };
+        self.root.map(|root| root.select(rank))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V, S, R> ExactSizeIterator for Shuffled<'a, K, V, S, R>
+where
+    S: Summary<K, V>,
+    R: Rng,
+{
+}
+
+impl<'a, K, V, S, R> FusedIterator for Shuffled<'a, K, V, S, R>
+where
+    S: Summary<K, V>,
+    R: Rng,
+{
+}
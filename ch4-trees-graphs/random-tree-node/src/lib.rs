@@ -1,194 +1,1268 @@
-#![allow(dead_code)]
-use rand::prelude::*;
+mod arena;
+mod concurrent;
+mod cursor;
+mod entry;
+mod iter;
+mod multiset;
+mod persistent;
+pub mod reservoir;
+mod set;
+mod summary;
+
+pub use arena::{
+    ArenaEntry, ArenaIntoIter, ArenaIter, ArenaIterMut, ArenaKeys, ArenaOccupiedEntry,
+    ArenaRandomTree, ArenaVacantEntry, ArenaValues, ArenaValuesMut,
+};
+pub use concurrent::ConcurrentRandomTree;
+pub use cursor::{Cursor, CursorMut};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use iter::{IntoIter, IntoIterBfs, Iter, IterBfs, IterMut, Keys, Shuffled, Values, ValuesMut};
+pub use multiset::RandomMultiset;
+pub use persistent::{PersistentIter, PersistentRandomTree};
+pub use set::RandomSet;
+pub use summary::{Count, Max, Measure, Min, Sum, Summary, TotalWeight};
+
+use rand::{prelude::*, seq::index};
 use std::{
+    borrow::Borrow,
     cmp::{Ord, Ordering},
-    collections::VecDeque,
-    fmt,
+    iter::FromIterator,
+    ops::{Bound, Deref, DerefMut, Index, RangeBounds},
+    ptr::NonNull,
 };
 
+// Weight-balance parameters, from Hirai & Yamamoto, "Balancing
+// weight-balanced trees". A node is balanced when neither child weighs more
+// than DELTA times the other, where the weight of a subtree is its size + 1.
+// That keeps the height below log_{4/3}(n + 1).
+const DELTA: usize = 3;
+const GAMMA: usize = 2;
+
+// For values that carry their own sampling weight, which a TotalWeight
+// summary keeps track of.
+// Weights that aren't finite and non-negative count as 0, so their entries
+// are never drawn.
+pub trait Weight {
+    fn weight(&self) -> f64;
+}
+
+impl Weight for f64 {
+    fn weight(&self) -> f64 {
+        *self
+    }
+}
+
+// A value with an explicit weight, for values that don't carry one:
+// RandomTree<K, Weighted<V>, TotalWeight> samples by weight.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Weighted<V> {
+    pub value: V,
+    pub weight: f64,
+}
+
+impl<V> Weighted<V> {
+    pub fn new(value: V, weight: f64) -> Self {
+        Self { value, weight }
+    }
+}
+
+impl<V> Weight for Weighted<V> {
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+}
+
 #[derive(Debug)]
-struct Node<K, V>
-where
-    K: fmt::Debug,
-    V: fmt::Debug,
-{
+struct Node<K, V, S = ()> {
     key: K,
     value: V,
-    descendants: Option<usize>, // Number of total children. If Some, has to be correct. Should be Some, after every pub fn.
-    left: Option<Box<Node<K, V>>>,
-    right: Option<Box<Node<K, V>>>,
+    size: usize, // Number of entries in this subtree. random goes by it.
+    summary: S,  // Summary of this subtree, on top of its size.
+    left: Option<Box<Node<K, V, S>>>,
+    right: Option<Box<Node<K, V, S>>>,
+}
+
+impl<K, V, S> Node<K, V, S> {
+    // Number of entries in this subtree.
+    fn size(&self) -> usize {
+        self.size
+    }
 }
 
-impl<K, V> Node<K, V>
+impl<K, V, S> Node<K, V, S>
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    S: Summary<K, V>,
 {
     fn new(key: K, value: V) -> Self {
         Self {
+            size: 1,
+            summary: S::of(&key, &value),
             key,
             value,
-            descendants: Some(0),
             left: None,
             right: None,
         }
     }
 
-    fn random(&self, rng: &mut ThreadRng) -> (&K, &V) {
-        debug_assert_eq!(
-            self.descendants,
-            Some(self.left_children() + self.right_children())
-        );
+    // Picks an entry uniformly, from its rank.
+    fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> (&K, &V) {
+        self.select(rng.gen_range(0, self.size()))
+    }
+
+    // Picks an entry in proportion to its measure. subtree gives the
+    // measure of a whole subtree, and own that of its root's entry alone.
+    // Assumes the measure of this subtree is > 0.
+    fn random_by<R, M, F, G>(&self, rng: &mut R, subtree: F, own: G) -> (&K, &V)
+    where
+        R: Rng + ?Sized,
+        M: Measure,
+        F: Fn(&Self) -> &M,
+        G: Fn(&Self) -> M,
+    {
+        let measure = |node: Option<&Self>| node.map_or(0.0, |node| subtree(node).measure());
+        let mut target = rng.gen::<f64>() * subtree(self).measure();
+        let mut current = self;
+        // The last node passed on the right with some measure on its left or
+        // in itself, in case rounding errors make target overshoot.
+        let mut passed = None;
+        loop {
+            let left_total = measure(current.left.as_deref());
+            if target < left_total {
+                current = current.left.as_ref().unwrap();
+                continue;
+            }
+            target -= left_total;
+            let own_total = own(current).measure();
+            if target < own_total {
+                return (&current.key, &current.value);
+            }
+            target -= own_total;
+            if left_total + own_total > 0.0 {
+                passed = Some(current);
+            }
+            match current.right.as_deref() {
+                Some(right) if measure(Some(right)) > 0.0 => current = right,
+                _ => break,
+            }
+        }
+        // Only reachable through rounding errors: returns the last entry
+        // with a measure > 0 up to here.
+        let mut current = passed.unwrap();
+        if own(current).measure() > 0.0 {
+            return (&current.key, &current.value);
+        }
+        current = current.left.as_ref().unwrap();
+        loop {
+            match current.right.as_deref() {
+                Some(right) if measure(Some(right)) > 0.0 => current = right,
+                _ if own(current).measure() > 0.0 => return (&current.key, &current.value),
+                _ => current = current.left.as_ref().unwrap(),
+            }
+        }
+    }
+
+    // Returns the i-th smallest entry of this subtree.
+    // Assumes i < size.
+    fn select(&self, mut i: usize) -> (&K, &V) {
+        let mut current = self;
+        loop {
+            let left = current.left_children();
+            match i.cmp(&left) {
+                Ordering::Less => current = current.left.as_ref().unwrap(),
+                Ordering::Equal => return (&current.key, &current.value),
+                Ordering::Greater => {
+                    i -= left + 1;
+                    current = current.right.as_ref().unwrap();
+                }
+            }
+        }
+    }
 
-        let i = rng.gen_range(0, self.descendants.unwrap() + 1);
+    fn left_children(&self) -> usize {
+        self.left.as_ref().map_or(0, |left| left.size())
+    }
 
-        // We only *need* to sample once, but this is easier to code.
-        // Time *complexity* doesn't change.
-        if i == 0 {
-            (&self.key, &self.value)
-        } else if i <= self.left_children() {
-            self.left.as_ref().unwrap().random(rng)
-        } else if i <= self.left_children() + self.right_children() {
-            self.right.as_ref().unwrap().random(rng)
+    fn right_children(&self) -> usize {
+        self.right.as_ref().map_or(0, |right| right.size())
+    }
+
+    fn left_balance_weight(&self) -> usize {
+        self.left_children() + 1
+    }
+
+    fn right_balance_weight(&self) -> usize {
+        self.right_children() + 1
+    }
+
+    // Recomputes size and summary, the latter in key order: left subtree,
+    // own entry, right subtree.
+    // Assumes the children's are correct.
+    fn update_counts(&mut self) {
+        self.size = self.left_children() + 1 + self.right_children();
+        let mut summary = S::of(&self.key, &self.value);
+        if let Some(left) = &self.left {
+            summary = left.summary.combine(&summary);
+        }
+        if let Some(right) = &self.right {
+            summary = summary.combine(&right.summary);
+        }
+        self.summary = summary;
+    }
+
+    fn rotate_left(mut self: Box<Self>) -> Box<Self> {
+        let mut new_root = self.right.take().unwrap();
+        self.right = new_root.left.take();
+        self.update_counts();
+        new_root.left = Some(self);
+        new_root.update_counts();
+        new_root
+    }
+
+    fn rotate_right(mut self: Box<Self>) -> Box<Self> {
+        let mut new_root = self.left.take().unwrap();
+        self.left = new_root.right.take();
+        self.update_counts();
+        new_root.right = Some(self);
+        new_root.update_counts();
+        new_root
+    }
+
+    fn subtree_balance_weight(subtree: &Option<Box<Self>>) -> usize {
+        subtree.as_ref().map_or(1, |node| node.size() + 1)
+    }
+
+    // Joins left, node and right into one balanced subtree. Every key in left
+    // has to be smaller than node's, and every key in right larger.
+    // node's own children are discarded.
+    // O(log(n)), or rather the difference in the heights of left and right.
+    fn link(left: Option<Box<Self>>, mut node: Box<Self>, right: Option<Box<Self>>) -> Box<Self> {
+        let left_weight = Self::subtree_balance_weight(&left);
+        let right_weight = Self::subtree_balance_weight(&right);
+        if left_weight > DELTA * right_weight {
+            let mut new_root = left.unwrap();
+            new_root.right = Some(Self::link(new_root.right.take(), node, right));
+            new_root.update_counts();
+            new_root.balance()
+        } else if right_weight > DELTA * left_weight {
+            let mut new_root = right.unwrap();
+            new_root.left = Some(Self::link(left, node, new_root.left.take()));
+            new_root.update_counts();
+            new_root.balance()
         } else {
-            unreachable!()
+            node.left = left;
+            node.right = right;
+            node.update_counts();
+            node
         }
     }
 
-    fn calc_descendants(&mut self) -> usize {
-        if self.descendants.is_none() {
-            self.left.as_mut().map(|child| child.calc_descendants());
-            self.right.as_mut().map(|child| child.calc_descendants());
-            self.descendants = Some(self.left_children() + self.right_children());
+    // Same as link, without a node in the middle.
+    fn concat(left: Option<Box<Self>>, right: Option<Box<Self>>) -> Option<Box<Self>> {
+        match right {
+            None => left,
+            Some(right) => {
+                let (min, rest) = right.pop_min();
+                Some(Self::link(left, min, rest))
+            }
         }
-        self.descendants.unwrap()
     }
 
-    fn left_children(&self) -> usize {
-        match &self.left {
-            Some(boxed_node) => 1 + boxed_node.descendants.unwrap(),
-            None => 0,
+    // Splits a subtree into the keys smaller than key, and the rest.
+    // O(log(n))
+    fn split<Q>(subtree: Option<Box<Self>>, key: &Q) -> (Option<Box<Self>>, Option<Box<Self>>)
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match subtree {
+            None => (None, None),
+            Some(mut node) => {
+                let (left, right) = (node.left.take(), node.right.take());
+                if key <= node.key.borrow() {
+                    let (smaller, rest) = Self::split(left, key);
+                    (smaller, Some(Self::link(rest, node, right)))
+                } else {
+                    let (smaller, rest) = Self::split(right, key);
+                    (Some(Self::link(left, node, smaller)), rest)
+                }
+            }
         }
     }
 
-    fn right_children(&self) -> usize {
-        match &self.right {
-            Some(boxed_node) => 1 + boxed_node.descendants.unwrap(),
-            None => 0,
+    // Keeps the entries that keep returns true for, calling it in key order,
+    // and hands the others to removed. The subtree is put back together
+    // bottom-up with link and concat, so every node is recounted once.
+    // O(n) in total, like any sequence of joins over a whole tree.
+    fn filter<F, G>(subtree: Option<Box<Self>>, keep: &mut F, removed: &mut G) -> Option<Box<Self>>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+        G: FnMut(K, V),
+    {
+        let mut node = subtree?;
+        let left = Self::filter(node.left.take(), keep, removed);
+        if keep(&node.key, &mut node.value) {
+            let right = Self::filter(node.right.take(), keep, removed);
+            Some(Self::link(left, node, right))
+        } else {
+            let Node {
+                key, value, right, ..
+            } = *node;
+            removed(key, value);
+            Self::concat(left, Self::filter(right, keep, removed))
+        }
+    }
+
+    fn leftmost(&self) -> &Self {
+        let mut current = self;
+        while let Some(left) = current.left.as_ref() {
+            current = left;
+        }
+        current
+    }
+
+    fn rightmost(&self) -> &Self {
+        let mut current = self;
+        while let Some(right) = current.right.as_ref() {
+            current = right;
+        }
+        current
+    }
+
+    // Builds a perfectly balanced subtree out of the next len entries.
+    // O(len)
+    fn build<I: Iterator<Item = (K, V)>>(entries: &mut I, len: usize) -> Option<Box<Self>> {
+        Self::build_from_nodes(
+            &mut entries.map(|(key, value)| Box::new(Self::new(key, value))),
+            len,
+        )
+    }
+
+    // Same as build, but reuses the nodes as they are, apart from their
+    // children.
+    // O(len)
+    fn build_from_nodes<I>(nodes: &mut I, len: usize) -> Option<Box<Self>>
+    where
+        I: Iterator<Item = Box<Self>>,
+    {
+        if len == 0 {
+            return None;
+        }
+        let left = Self::build_from_nodes(nodes, len / 2);
+        let mut node = nodes.next().unwrap();
+        let right = Self::build_from_nodes(nodes, len - len / 2 - 1);
+
+        node.left = left;
+        node.right = right;
+        node.update_counts();
+        Some(node)
+    }
+
+    // Moves the nodes of this subtree into nodes, in key order.
+    // O(n)
+    fn flatten(mut self: Box<Self>, nodes: &mut Vec<Box<Self>>) {
+        if let Some(left) = self.left.take() {
+            left.flatten(nodes);
+        }
+        let right = self.right.take();
+        nodes.push(self);
+        if let Some(right) = right {
+            right.flatten(nodes);
         }
     }
 
-    fn iter(&self) -> Iter<'_, K, V> {
-        let mut queue = VecDeque::new();
-        queue.push_back(self);
-        Iter { queue }
+    // Restores the weight invariant at this node, assuming both children
+    // are balanced and at most one insertion or deletion away from being
+    // balanced with each other.
+    fn balance(self: Box<Self>) -> Box<Self> {
+        self.balance_with(|_| (0, 0))
     }
 
-    fn into_iter(self) -> IntoIter<K, V> {
-        let mut queue = VecDeque::<Node<K, V>>::new();
-        queue.push_back(self);
-        IntoIter { queue }
+    // Same as balance, but pending gives the weight that is still to be
+    // added to the left and right of a node, by an insertion below it.
+    fn balance_with<F>(mut self: Box<Self>, pending: F) -> Box<Self>
+    where
+        F: Fn(&Self) -> (usize, usize),
+    {
+        let weights = |node: &Self| {
+            let (left, right) = pending(node);
+            (
+                node.left_balance_weight() + left,
+                node.right_balance_weight() + right,
+            )
+        };
+        let (left, right) = weights(&self);
+        if right > DELTA * left {
+            let (inner, outer) = weights(self.right.as_ref().unwrap());
+            if inner >= GAMMA * outer {
+                self.right = Some(self.right.take().unwrap().rotate_right());
+            }
+            self.rotate_left()
+        } else if left > DELTA * right {
+            let (outer, inner) = weights(self.left.as_ref().unwrap());
+            if inner >= GAMMA * outer {
+                self.left = Some(self.left.take().unwrap().rotate_left());
+            }
+            self.rotate_right()
+        } else {
+            self
+        }
+    }
+
+    // Detaches the minimum node of this subtree. Returns it, along with
+    // what is left of the subtree.
+    fn pop_min(mut self: Box<Self>) -> (Box<Self>, Option<Box<Self>>) {
+        match self.left.take() {
+            None => {
+                let rest = self.right.take();
+                self.update_counts();
+                (self, rest)
+            }
+            Some(left) => {
+                let (min, rest) = left.pop_min();
+                self.left = rest;
+                self.update_counts();
+                (min, Some(self.balance()))
+            }
+        }
+    }
+
+    // Joins the two children of a removed node, by splicing in the
+    // in-order successor as their new parent.
+    fn splice(left: Option<Box<Self>>, right: Option<Box<Self>>) -> Option<Box<Self>> {
+        match (left, right) {
+            (None, right) => right,
+            (left, None) => left,
+            (Some(left), Some(right)) => {
+                let (mut successor, rest) = right.pop_min();
+                successor.left = Some(left);
+                successor.right = rest;
+                successor.update_counts();
+                Some(successor.balance())
+            }
+        }
+    }
+
+    fn follow(&self, path: &[Ordering]) -> &Self {
+        path.iter().fold(self, |node, direction| match direction {
+            Ordering::Less => node.left.as_ref().unwrap(),
+            _ => node.right.as_ref().unwrap(),
+        })
+    }
+
+    fn follow_mut(&mut self, path: &[Ordering]) -> &mut Self {
+        path.iter().fold(self, |node, direction| match direction {
+            Ordering::Less => node.left.as_mut().unwrap(),
+            _ => node.right.as_mut().unwrap(),
+        })
+    }
+
+    // Calls f on the node at the end of path, and fixes the totals and
+    // summaries of every node along the way.
+    fn modify<R>(&mut self, path: &[Ordering], f: impl FnOnce(&mut Self) -> R) -> R {
+        let result = match path.split_first() {
+            None => f(self),
+            Some((Ordering::Less, rest)) => self.left.as_mut().unwrap().modify(rest, f),
+            Some((_, rest)) => self.right.as_mut().unwrap().modify(rest, f),
+        };
+        self.update_counts();
+        result
+    }
+
+    // Summary of the entries with indices in [start, end) of this subtree.
+    // Assumes start < end.
+    // O(log(n)): past the first split, one side of every range is open, so
+    // only one child per level has to be looked into.
+    fn summarize(&self, start: usize, end: usize) -> S {
+        if start == 0 && end >= self.size() {
+            return self.summary.clone();
+        }
+        let left = self.left_children();
+        let mut result = S::empty();
+        if start < left {
+            result = self.left.as_ref().unwrap().summarize(start, end.min(left));
+        }
+        if start <= left && left < end {
+            result = result.combine(&S::of(&self.key, &self.value));
+        }
+        if end > left + 1 {
+            let right = self.right.as_ref().unwrap();
+            let start = start.saturating_sub(left + 1);
+            result = result.combine(&right.summarize(start, end - left - 1));
+        }
+        result
     }
 }
 
-#[derive(Debug)]
-struct RandomTree<K, V>
+// Keeps the last of each run of equal keys.
+fn dedup_sorted<K: Eq, V>(entries: &mut Vec<(K, V)>) {
+    entries.dedup_by(|later, earlier| {
+        let duplicate = later.0 == earlier.0;
+        if duplicate {
+            std::mem::swap(later, earlier);
+        }
+        duplicate
+    });
+}
+
+// Converts a range of keys to the half-open range of their indices, given
+// the number of entries and a rank function like RandomTree::rank.
+fn index_bounds<K, T, F>(range: &T, len: usize, rank: F) -> (usize, usize)
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    T: RangeBounds<K>,
+    F: Fn(&K) -> Result<usize, usize>,
 {
-    root: Option<Box<Node<K, V>>>,
+    let below = |key| match rank(key) {
+        Ok(i) | Err(i) => i,
+    };
+    let up_to = |key| match rank(key) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    };
+    let start = match range.start_bound() {
+        Bound::Included(key) => below(key),
+        Bound::Excluded(key) => up_to(key),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(key) => up_to(key),
+        Bound::Excluded(key) => below(key),
+        Bound::Unbounded => len,
+    };
+    (start, end)
+}
+
+// How random_where found its entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // Drew random entries until one matched.
+    Rejection,
+    // Gave up on drawing, and went through every entry.
+    Scan,
+}
+
+// S is the summary kept in every node; see Summary. With the default of (),
+// the values can be mutated freely. With any other summary they can only be
+// changed through update, which keeps the summaries up to date, or through
+// the guard get_mut returns with TotalWeight.
+#[derive(Debug)]
+pub struct RandomTree<K, V, S = ()> {
+    root: Option<Box<Node<K, V, S>>>,
 }
 
 impl<K, V> RandomTree<K, V>
 where
-    K: Ord + fmt::Debug,
-    V: fmt::Debug,
+    K: Ord,
 {
     pub fn new() -> Self {
         Self { root: None }
     }
 
-    // Returns None iff the tree is empty.
+    // Builds a perfectly balanced tree from entries sorted by key.
+    // For equal keys, the last value wins, like with insert.
+    // Panics if the keys are out of order.
+    // O(n)
+    pub fn from_sorted_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries = iter.into_iter().collect::<Vec<_>>();
+        assert!(
+            entries.windows(2).all(|pair| pair[0].0 <= pair[1].0),
+            "keys are not sorted"
+        );
+        dedup_sorted(&mut entries);
+        let len = entries.len();
+        Self {
+            root: Node::build(&mut entries.into_iter(), len),
+        }
+    }
+
+    // Gets the entry for key, to insert or update it in place.
+    // O(log(n)), and O(log(n)) more to insert into a vacant entry.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        // Safe code can't return the borrow of the found node while keeping
+        // the tree for the vacant case, so the lookup hands out a pointer.
+        match self.find_mut(&key).map(NonNull::from) {
+            // SAFETY: the node was borrowed from self, which is unused here
+            // and stays borrowed for as long as the entry.
+            Some(node) => Entry::Occupied(OccupiedEntry::new(unsafe { &mut *node.as_ptr() })),
+            None => Entry::Vacant(VacantEntry::new(self, key)),
+        }
+    }
+
+    // Inserts an entry whose key is absent, and returns its value.
+    // O(log(n))
+    fn insert_new(&mut self, key: K, value: V) -> &mut V {
+        let (_, value) = Self::insert_aux(&mut self.root, key, value);
+        // SAFETY: insert_aux doesn't move or borrow the new node mutably
+        // once it has placed it, and the pointer was derived from self,
+        // which stays borrowed for as long as the result.
+        unsafe { &mut *value.as_ptr() }
+    }
+
     // O(log(n))
-    pub fn random(&self, rng: &mut ThreadRng) -> Option<(&K, &V)> {
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find_mut(key).map(|node| &mut node.value)
+    }
+
+    // Same as select, but the value is mutable.
+    // O(log(n))
+    pub fn get_index_mut(&mut self, mut i: usize) -> Option<(&K, &mut V)> {
+        let mut current = self.root.as_mut()?;
+        if i >= current.size() {
+            return None;
+        }
+        loop {
+            let left = current.left_children();
+            match i.cmp(&left) {
+                Ordering::Less => current = current.left.as_mut().unwrap(),
+                Ordering::Equal => return Some((&current.key, &mut current.value)),
+                Ordering::Greater => {
+                    i -= left + 1;
+                    current = current.right.as_mut().unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut::new(self.root.as_deref_mut())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut::new(self.iter_mut())
+    }
+}
+
+// Trees whose entries are weighed by their values, e.g. by Weighted ones
+// for explicit weights. TotalWeight keeps the weights of every subtree up to
+// date, and random_weighted samples by them.
+impl<K, V> RandomTree<K, V, TotalWeight>
+where
+    K: Ord,
+    V: Weight,
+{
+    // Same as get_mut on trees without a summary, but the weights above the
+    // entry are brought up to date once the returned guard is dropped.
+    // O(log(n)), plus O(log(n)) for every dereference.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<WeightedMut<'_, K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let path = self.path_to(key)?;
+        Some(WeightedMut {
+            root: self.root.as_mut().unwrap(),
+            path,
+        })
+    }
+
+    // Picks each entry with probability proportional to its weight.
+    // Returns None iff the total weight of the tree is 0.
+    // O(log(n))
+    pub fn random_weighted<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
+        self.random_by_measure(rng)
+    }
+
+    // Returns the weight of the entry associated with key.
+    // O(log(n))
+    pub fn weight<Q>(&self, key: &Q) -> Option<f64>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key)
+            .map(|node| TotalWeight::of(&node.key, &node.value).0)
+    }
+
+    // Sum of the weights of every entry.
+    // O(1)
+    pub fn total_weight(&self) -> f64 {
+        self.summary().0
+    }
+}
+
+impl<K, V, S> RandomTree<K, V, S>
+where
+    K: Ord,
+    S: Summary<K, V>,
+{
+    // Same as new, for trees with a summary, which has to be named:
+    // RandomTree::<K, V, Sum<V>>::with_summary().
+    pub fn with_summary() -> Self {
+        Self { root: None }
+    }
+
+    // O(1)
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |root| root.size())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // Returns None iff the tree is empty.
+    // O(log(n)), since the tree is weight-balanced.
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
         self.root.as_ref().map(|root_node| root_node.random(rng))
     }
 
+    // Picks min(amount, len) distinct entries, in random order.
+    // O(amount * log(n))
+    pub fn sample<R: Rng + ?Sized>(&self, amount: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        match self.root.as_ref() {
+            None => Vec::new(),
+            Some(root) => {
+                let len = self.len();
+                index::sample(rng, len, amount.min(len))
+                    .into_iter()
+                    .map(|i| root.select(i))
+                    .collect()
+            }
+        }
+    }
+
+    // Same as sample, but the entries come sorted by key.
+    // O(amount * log(n))
+    pub fn sample_sorted<R: Rng + ?Sized>(&self, amount: usize, rng: &mut R) -> Vec<(&K, &V)> {
+        match self.root.as_ref() {
+            None => Vec::new(),
+            Some(root) => {
+                let len = self.len();
+                let mut ranks = index::sample(rng, len, amount.min(len)).into_vec();
+                ranks.sort_unstable();
+                ranks.into_iter().map(|i| root.select(i)).collect()
+            }
+        }
+    }
+
     // Returns the old value associated with K, or None if the key is new.
     // O(log(n))
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        match self.find_mut(&key) {
-            Some(node) => Some(std::mem::replace(&mut node.value, value)),
-            None => {
-                Self::insert_aux(&mut self.root, key, value);
-                None
+        Self::insert_aux(&mut self.root, key, value).0
+    }
+
+    // O(log(n))
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.find(key).map(|node| &node.value)
+    }
+
+    // O(log(n))
+    pub fn has<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    // Returns the value associated with K, or None if the key was absent.
+    // O(log(n))
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Self::remove_by(&mut self.root, &mut |node| key.cmp(node.key.borrow())).map(|(_, v)| v)
+    }
+
+    // Returns the i-th smallest entry, counting from 0.
+    // O(log(n))
+    pub fn select(&self, i: usize) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .filter(|root| i < root.size())
+            .map(|root| root.select(i))
+    }
+
+    // Returns Ok with the position of key in sorted order if it is present,
+    // or Err with the position it would be inserted at.
+    // O(log(n))
+    pub fn rank<Q>(&self, key: &Q) -> Result<usize, usize>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut smaller = 0;
+        let mut current = &self.root;
+        while let Some(current_node) = current.as_ref() {
+            match key.cmp(current_node.key.borrow()) {
+                Ordering::Equal => return Ok(smaller + current_node.left_children()),
+                Ordering::Less => current = &current_node.left,
+                Ordering::Greater => {
+                    smaller += current_node.left_children() + 1;
+                    current = &current_node.right;
+                }
             }
         }
+        Err(smaller)
     }
 
+    // The entry with the largest key less than or equal to key.
     // O(log(n))
-    pub fn get(&self, key: &K) -> Option<&V> {
-        self.find(key).map(|node| &node.value)
+    pub fn floor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.closest(key, Ordering::Less, true)
     }
 
+    // The entry with the smallest key greater than or equal to key.
     // O(log(n))
-    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        self.find_mut(key).map(|node| &mut node.value)
+    pub fn ceiling<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.closest(key, Ordering::Greater, true)
     }
 
+    // The entry with the largest key less than key.
     // O(log(n))
-    pub fn has(&self, key: &K) -> bool {
-        self.get(key).is_some()
+    pub fn predecessor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.closest(key, Ordering::Less, false)
+    }
+
+    // The entry with the smallest key greater than key.
+    // O(log(n))
+    pub fn successor<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.closest(key, Ordering::Greater, false)
+    }
+
+    // O(log(n))
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let first = self.root.as_ref()?.leftmost();
+        Some((&first.key, &first.value))
+    }
+
+    // O(log(n))
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let last = self.root.as_ref()?.rightmost();
+        Some((&last.key, &last.value))
+    }
+
+    // Removes and returns the entry with the smallest key.
+    // O(log(n))
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        self.remove_index(0)
+    }
+
+    // Removes and returns the entry with the largest key.
+    // O(log(n))
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.remove_index(self.len().checked_sub(1)?)
+    }
+
+    // Picks uniformly among the entries that satisfy pred, and says how.
+    // Draws up to REJECTION_TRIES random entries first, so if a fraction p
+    // of the entries match, that takes O(log(n) / p) and falls back with
+    // probability (1 - p)^REJECTION_TRIES. The fallback scans every entry.
+    // Returns None, with Strategy::Scan, iff no entry matches.
+    pub fn random_where<F, R>(&self, mut pred: F, rng: &mut R) -> (Option<(&K, &V)>, Strategy)
+    where
+        F: FnMut(&K, &V) -> bool,
+        R: Rng + ?Sized,
+    {
+        const REJECTION_TRIES: usize = 32;
+
+        if let Some(root) = self.root.as_ref() {
+            for _ in 0..REJECTION_TRIES {
+                let (key, value) = root.random(rng);
+                if pred(key, value) {
+                    return (Some((key, value)), Strategy::Rejection);
+                }
+            }
+        }
+        let matching = self.iter().filter(|(key, value)| pred(key, value));
+        (reservoir::choose(matching, rng), Strategy::Scan)
+    }
+
+    // Picks uniformly among the keys inside range.
+    // Returns None iff there are none.
+    // O(log(n))
+    pub fn random_in_range<T, R>(&self, range: T, rng: &mut R) -> Option<(&K, &V)>
+    where
+        T: RangeBounds<K>,
+        R: Rng + ?Sized,
+    {
+        let (start, end) = self.index_bounds(&range);
+        if start < end {
+            self.select(rng.gen_range(start, end))
+        } else {
+            None
+        }
+    }
+
+    // Number of keys inside range.
+    // O(log(n))
+    pub fn count_range<T: RangeBounds<K>>(&self, range: T) -> usize {
+        let (start, end) = self.index_bounds(&range);
+        end.saturating_sub(start)
+    }
+
+    // Summary of every entry.
+    // O(1), plus a clone.
+    pub fn summary(&self) -> S {
+        self.root
+            .as_ref()
+            .map_or_else(S::empty, |root| root.summary.clone())
     }
 
+    // Summary of the entries with keys inside range, e.g. the sum of their
+    // values with Sum.
+    // O(log(n))
+    pub fn summarize<T: RangeBounds<K>>(&self, range: T) -> S {
+        let (start, end) = self.index_bounds(&range);
+        match self.root.as_ref() {
+            Some(root) if start < end => root.summarize(start, end),
+            _ => S::empty(),
+        }
+    }
+
+    // Picks each entry in proportion to its share of the measure, e.g. its
+    // weight with TotalWeight. With Count, this is the same as random.
+    // Returns None iff the measure of the tree is 0.
+    // O(log(n))
+    pub fn random_by_measure<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)>
+    where
+        S: Measure,
+    {
+        self.root
+            .as_ref()
+            .filter(|root_node| root_node.summary.measure() > 0.0)
+            .map(|root_node| {
+                root_node.random_by(
+                    rng,
+                    |node| &node.summary,
+                    |node| S::of(&node.key, &node.value),
+                )
+            })
+    }
+
+    // Calls f on the value associated with key, then brings the summaries
+    // above it up to date. Returns None if the key is absent.
+    // O(log(n))
+    pub fn update<Q, F, T>(&mut self, key: &Q, f: F) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        F: FnOnce(&mut V) -> T,
+    {
+        let path = self.path_to(key)?;
+        let root = self.root.as_mut().unwrap();
+        Some(root.modify(&path, |node| f(&mut node.value)))
+    }
+
+    // Removes and returns the i-th smallest entry.
+    // O(log(n))
+    pub fn remove_index(&mut self, mut i: usize) -> Option<(K, V)> {
+        if i >= self.root.as_ref()?.size() {
+            return None;
+        }
+        Self::remove_by(&mut self.root, &mut |node| {
+            let left = node.left_children();
+            let ordering = i.cmp(&left);
+            if ordering == Ordering::Greater {
+                i -= left + 1;
+            }
+            ordering
+        })
+    }
+
+    // Keeps only the entries that f returns true for. f sees the entries in
+    // key order, and can change the values it keeps.
+    // O(n), rather than the O(m * log(n)) of removing m entries one by one.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.root = Node::filter(self.root.take(), &mut f, &mut |_, _| ());
+    }
+
+    // Same as retain, but removes the entries that pred returns true for,
+    // and returns them in key order.
     // O(n)
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        let branch = self.take_branch(key)?;
-        let mut kv_pairs = branch.into_iter();
-        let removed = kv_pairs.next().unwrap();
-        for (k, v) in kv_pairs {
-            self.insert(k, v);
+    pub fn extract_if<F>(&mut self, mut pred: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut extracted = Vec::new();
+        let mut keep = |key: &K, value: &mut V| !pred(key, value);
+        let mut removed = |key, value| extracted.push((key, value));
+        self.root = Node::filter(self.root.take(), &mut keep, &mut removed);
+        extracted
+    }
+
+    // Removes every key in keys, and returns how many were present.
+    // O(m * log(n)) for m keys when that is less than n, in which case they
+    // are removed one by one. Otherwise O(n + m * log(m)), in one pass.
+    pub fn remove_all<'q, Q, I>(&mut self, keys: I) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        let len = self.len();
+        let depth = (usize::BITS - len.leading_zeros()) as usize;
+        if keys.len() * depth < len {
+            return keys
+                .into_iter()
+                .filter(|key| self.remove(*key).is_some())
+                .count();
         }
-        Some(removed.1)
+
+        keys.sort_unstable();
+        let mut keys = keys.into_iter().peekable();
+        let mut keep = |key: &K, _: &mut V| {
+            while keys.peek().is_some_and(|next| *next < key.borrow()) {
+                keys.next();
+            }
+            keys.peek().is_none_or(|next| *next != key.borrow())
+        };
+        self.root = Node::filter(self.root.take(), &mut keep, &mut |_, _| ());
+        len - self.len()
+    }
+
+    // Removes and returns a uniformly random entry.
+    // Returns None iff the tree is empty.
+    // O(log(n)): the rank is drawn once, and one descent finds and removes it.
+    pub fn pop_random<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<(K, V)> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+        self.remove_index(rng.gen_range(0, len))
+    }
+
+    // Removes min(amount, len) random entries, and returns them in the order
+    // they were drawn.
+    // O(amount * log(n))
+    pub fn pop_random_k<R: Rng + ?Sized>(&mut self, amount: usize, rng: &mut R) -> Vec<(K, V)> {
+        let amount = amount.min(self.len());
+        (0..amount).filter_map(|_| self.pop_random(rng)).collect()
     }
 
-    fn insert_aux(parents_ref: &mut Option<Box<Node<K, V>>>, key: K, value: V) {
-        match parents_ref {
+    // Replaces the value in place if key is already present. Returns the
+    // old value, and a pointer to the one now associated with key.
+    // Every node on the way down is rebalanced before moving past it, as if
+    // the new node were already below it. That keeps the tree balanced even
+    // if key turns out to be present, and once the node is in place, only
+    // sizes and summaries are updated on the way back up.
+    fn insert_aux(
+        parents_ref: &mut Option<Box<Node<K, V, S>>>,
+        key: K,
+        value: V,
+    ) -> (Option<V>, NonNull<V>) {
+        let pending = |parent: &Node<K, V, S>| match key.cmp(&parent.key) {
+            Ordering::Less => (1, 0),
+            Ordering::Greater => (0, 1),
+            Ordering::Equal => (0, 0),
+        };
+        *parents_ref = parents_ref
+            .take()
+            .map(|parent| parent.balance_with(pending));
+        let new_parent = match parents_ref {
             None => {
-                let node = Node::new(key, value);
-                *parents_ref = Some(Box::new(node));
-            }
-            Some(new_parent) => {
-                *new_parent.descendants.as_mut().unwrap() += 1;
-                match (&key).cmp(&new_parent.key) {
-                    Ordering::Less => Self::insert_aux(&mut new_parent.left, key, value),
-                    Ordering::Greater => Self::insert_aux(&mut new_parent.right, key, value),
-                    Ordering::Equal => unreachable!(),
+                let node = parents_ref.insert(Box::new(Node::new(key, value)));
+                return (None, NonNull::from(&mut node.value));
+            }
+            Some(new_parent) => new_parent,
+        };
+        let result = match key.cmp(&new_parent.key) {
+            Ordering::Less => Self::insert_aux(&mut new_parent.left, key, value),
+            Ordering::Greater => Self::insert_aux(&mut new_parent.right, key, value),
+            Ordering::Equal => {
+                let old = std::mem::replace(&mut new_parent.value, value);
+                new_parent.update_counts();
+                return (Some(old), NonNull::from(&mut new_parent.value));
+            }
+        };
+        new_parent.update_counts();
+        result
+    }
+
+    // Moves every key greater than or equal to key into a new tree.
+    // O(log(n))
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let (smaller, rest) = Node::split(self.root.take(), key);
+        self.root = smaller;
+        Self { root: rest }
+    }
+
+    // Moves every entry of other into self, leaving other empty. On equal
+    // keys, the value from other wins.
+    // O(log(n)) if the key ranges don't overlap, O(n) otherwise.
+    pub fn append(&mut self, other: &mut Self) {
+        let (left, right) = (self.root.take(), other.root.take());
+        self.root = match (left, right) {
+            (None, only) | (only, None) => only,
+            (Some(left), Some(right)) => {
+                if left.rightmost().key < right.leftmost().key {
+                    Node::concat(Some(left), Some(right))
+                } else if right.rightmost().key < left.leftmost().key {
+                    Node::concat(Some(right), Some(left))
+                } else {
+                    // Merges the nodes themselves, so that nothing stored in
+                    // them is lost.
+                    let mut merged = Vec::with_capacity(left.size() + right.size());
+                    let (mut left_nodes, mut right_nodes) = (Vec::new(), Vec::new());
+                    left.flatten(&mut left_nodes);
+                    right.flatten(&mut right_nodes);
+                    let mut left = left_nodes.into_iter().peekable();
+                    let mut right = right_nodes.into_iter().peekable();
+                    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+                        match l.key.cmp(&r.key) {
+                            Ordering::Less => merged.push(left.next().unwrap()),
+                            Ordering::Greater => merged.push(right.next().unwrap()),
+                            Ordering::Equal => {
+                                left.next();
+                                merged.push(right.next().unwrap());
+                            }
+                        }
+                    }
+                    merged.extend(left.chain(right));
+                    let len = merged.len();
+                    Node::build_from_nodes(&mut merged.into_iter(), len)
                 }
             }
+        };
+    }
+
+    // Concatenates two trees. Every key in left has to be smaller than every
+    // key in right.
+    // Panics if the key ranges overlap.
+    // O(log(n))
+    pub fn join(mut left: Self, right: Self) -> Self {
+        if let (Some(l), Some(r)) = (left.root.as_ref(), right.root.as_ref()) {
+            assert!(l.rightmost().key < r.leftmost().key, "key ranges overlap");
         }
+        left.root = Node::concat(left.root.take(), right.root);
+        left
+    }
+
+    fn index_bounds<T: RangeBounds<K>>(&self, range: &T) -> (usize, usize) {
+        index_bounds(range, self.len(), |key| self.rank(key))
+    }
+
+    // Removes the node that locate leads to. locate is called once on each
+    // node along the path, and says whether the target is in the left
+    // subtree, the right one, or is the node itself.
+    // Only the nodes on that path have their counts updated.
+    fn remove_by<F>(parents_ref: &mut Option<Box<Node<K, V, S>>>, locate: &mut F) -> Option<(K, V)>
+    where
+        F: FnMut(&Node<K, V, S>) -> Ordering,
+    {
+        let ordering = locate(parents_ref.as_ref()?);
+        if ordering == Ordering::Equal {
+            let removed = *parents_ref.take().unwrap();
+            *parents_ref = Node::splice(removed.left, removed.right);
+            return Some((removed.key, removed.value));
+        }
+
+        let parent = parents_ref.as_mut().unwrap();
+        let removed = match ordering {
+            Ordering::Less => Self::remove_by(&mut parent.left, locate),
+            _ => Self::remove_by(&mut parent.right, locate),
+        }?;
+        parent.update_counts();
+        *parents_ref = parents_ref.take().map(Node::balance);
+        Some(removed)
+    }
+
+    // The nearest entry to key on the side given by side, which is Less or
+    // Greater. key itself counts iff inclusive.
+    fn closest<Q>(&self, key: &Q, side: Ordering, inclusive: bool) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut best = None;
+        let mut current = &self.root;
+        while let Some(current_node) = current.as_ref() {
+            let ordering = current_node.key.borrow().cmp(key);
+            if ordering == Ordering::Equal && inclusive {
+                return Some((&current_node.key, &current_node.value));
+            }
+            // Every node on the wanted side is a candidate, and the next
+            // one has to be closer to key.
+            if ordering == side {
+                best = Some(current_node);
+            }
+            current = match (ordering, side) {
+                (Ordering::Less, _) | (Ordering::Equal, Ordering::Greater) => &current_node.right,
+                _ => &current_node.left,
+            };
+        }
+        best.map(|node| (&node.key, &node.value))
     }
 
     // Returns a reference to the entire node associated with key, if it
     // exists.
-    fn find(&self, key: &K) -> Option<&Node<K, V>> {
+    fn find<Q>(&self, key: &Q) -> Option<&Node<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut current = &self.root;
+        while let Some(current_node) = current.as_ref() {
+            match key.cmp(current_node.key.borrow()) {
+                Ordering::Equal => return Some(current_node),
+                Ordering::Less => current = &current_node.left,
+                Ordering::Greater => current = &current_node.right,
+            }
+        }
+        None
+    }
+
+    // The directions from the root to the node associated with key.
+    fn path_to<Q>(&self, key: &Q) -> Option<Vec<Ordering>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut path = Vec::new();
         let mut current = &self.root;
         while let Some(current_node) = current.as_ref() {
-            match key.cmp(&current_node.key) {
-                Ordering::Equal => return Some(&current_node),
+            let ordering = key.cmp(current_node.key.borrow());
+            match ordering {
+                Ordering::Equal => return Some(path),
                 Ordering::Less => current = &current_node.left,
                 Ordering::Greater => current = &current_node.right,
             }
+            path.push(ordering);
         }
         None
     }
 
     // Same as find, but mutable.
-    fn find_mut(&mut self, key: &K) -> Option<&mut Node<K, V>> {
+    fn find_mut<Q>(&mut self, key: &Q) -> Option<&mut Node<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
         let mut current = &mut self.root;
         while let Some(current_node) = current.as_mut() {
-            match key.cmp(&current_node.key) {
+            match key.cmp(current_node.key.borrow()) {
                 Ordering::Equal => return Some(current_node),
                 Ordering::Less => current = &mut current_node.left,
                 Ordering::Greater => current = &mut current_node.right,
@@ -197,127 +1271,208 @@ where
         None
     }
 
-    // Cuts a branch off the tree and returns it.
-    fn take_branch(&mut self, key: &K) -> Option<Box<Node<K, V>>> {
-        // reference to the pointer of the node we're inspecting.
-        let mut current_ptr: &mut Option<Box<Node<K, V>>> = &mut self.root;
-        let ret;
-        loop {
-            match current_ptr.take() {
-                None => {
-                    ret = None;
-                    break;
-                }
-                Some(mut boxed_node) => match key.cmp(&boxed_node.key) {
-                    Ordering::Equal => {
-                        ret = Some(boxed_node);
-                        break;
-                    }
-                    Ordering::Less => {
-                        boxed_node.descendants = None;
-                        *current_ptr = Some(boxed_node);
-                        current_ptr = &mut current_ptr.as_mut().unwrap().left;
-                    }
-                    Ordering::Greater => {
-                        boxed_node.descendants = None;
-                        *current_ptr = Some(boxed_node);
-                        current_ptr = &mut current_ptr.as_mut().unwrap().right;
-                    }
-                },
-            }
-        }
-        self.root.as_mut().map(|node| node.calc_descendants());
-        ret
+    // Iterates in key order.
+    pub fn iter(&self) -> Iter<'_, K, V, S> {
+        Iter::new(self.root.as_deref())
     }
 
-    pub fn iter(&self) -> Iter<'_, K, V> {
-        let mut queue = VecDeque::new();
-        if let Some(root) = self.root.as_ref() {
-            queue.push_back(&**root);
+    pub fn keys(&self) -> Keys<'_, K, V, S> {
+        Keys::new(self.iter())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, S> {
+        Values::new(self.iter())
+    }
+
+    // Iterates in key order over the keys inside range.
+    // O(log(n)) to set up.
+    pub fn range<T: RangeBounds<K>>(&self, range: T) -> Iter<'_, K, V, S> {
+        let (start, end) = self.index_bounds(&range);
+        Iter::range(self.root.as_deref(), start, end)
+    }
+
+    // A cursor on key, or on the first key above it if it is absent.
+    // O(log(n))
+    pub fn cursor_at<Q>(&self, key: &Q) -> Cursor<'_, K, V, S>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.rank(key) {
+            Ok(i) | Err(i) => Cursor::new(self, i),
         }
-        Iter { queue }
     }
 
-    pub fn into_iter(mut self) -> IntoIter<K, V> {
-        let mut queue = VecDeque::new();
-        if let Some(boxed_root) = self.root.take() {
-            queue.push_back(*boxed_root);
+    // A cursor on the i-th smallest entry, or on the ghost if i >= len.
+    // O(log(n))
+    pub fn cursor_at_index(&self, i: usize) -> Cursor<'_, K, V, S> {
+        Cursor::new(self, i)
+    }
+
+    // Same as cursor_at, but the cursor can change the tree.
+    // O(log(n))
+    pub fn cursor_mut_at<Q>(&mut self, key: &Q) -> CursorMut<'_, K, V, S>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.rank(key) {
+            Ok(i) | Err(i) => CursorMut::new(self, i),
         }
-        IntoIter { queue }
+    }
+
+    // Same as cursor_at_index, but the cursor can change the tree.
+    // O(log(n))
+    pub fn cursor_mut_at_index(&mut self, i: usize) -> CursorMut<'_, K, V, S> {
+        CursorMut::new(self, i)
+    }
+
+    // Iterates over every entry once, in uniformly random order. Pass the
+    // rng by &mut to keep using it afterwards.
+    // O(log(n)) per entry, and O(1) to set up.
+    pub fn shuffled<R: Rng>(&self, rng: R) -> Shuffled<'_, K, V, S, R> {
+        Shuffled::new(self.root.as_deref(), rng)
+    }
+
+    // Iterates in breadth-first order.
+    pub fn iter_bfs(&self) -> IterBfs<'_, K, V, S> {
+        IterBfs::new(self.root.as_deref())
+    }
+
+    pub fn into_iter_bfs(self) -> IntoIterBfs<K, V, S> {
+        IntoIterBfs::new(self.root)
     }
 }
 
-// These are BFS iterators.
-// The benefit of that is that, when reinserting nodes after taking
-// a branch, we reinsert them in almost the same order they had
-// originally.
-// A shuffling iterator would possibly be better.
-// A DFS iterator would definitely be worse.
-struct Iter<'a, K, V>
+impl<K, V, S> Default for RandomTree<K, V, S>
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    K: Ord,
+    S: Summary<K, V>,
 {
-    queue: VecDeque<&'a Node<K, V>>,
+    fn default() -> Self {
+        Self::with_summary()
+    }
 }
 
-struct IntoIter<K, V>
+impl<K, V, S> FromIterator<(K, V)> for RandomTree<K, V, S>
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    K: Ord,
+    S: Summary<K, V>,
 {
-    queue: VecDeque<Node<K, V>>,
+    // Sorts first, so the tree comes out perfectly balanced.
+    // O(n * log(n)), or O(n) if the entries are already sorted.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries = iter.into_iter().collect::<Vec<_>>();
+        // Stable, so that the last of equal keys stays last.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        dedup_sorted(&mut entries);
+        let len = entries.len();
+        Self {
+            root: Node::build(&mut entries.into_iter(), len),
+        }
+    }
 }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V>
+impl<K, V, S> Extend<(K, V)> for RandomTree<K, V, S>
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    K: Ord,
+    S: Summary<K, V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K, V, S> IntoIterator for RandomTree<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, S>;
+
+    // Iterates in key order.
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self.root)
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a RandomTree<K, V, S>
+where
+    K: Ord,
+    S: Summary<K, V>,
 {
     type Item = (&'a K, &'a V);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.queue.pop_front().map(|current_node| {
-            if let Some(left) = current_node.left.as_ref() {
-                self.queue.push_back(left);
-            }
-            if let Some(right) = current_node.right.as_ref() {
-                self.queue.push_back(right);
-            }
-            (&current_node.key, &current_node.value)
-        })
+    type IntoIter = Iter<'a, K, V, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
-impl<K, V> Iterator for IntoIter<K, V>
+impl<'a, K, V> IntoIterator for &'a mut RandomTree<K, V>
 where
-    K: fmt::Debug,
-    V: fmt::Debug,
+    K: Ord,
 {
-    type Item = (K, V);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.queue.pop_front().map(|mut current_node| {
-            if let Some(boxed_left) = current_node.left.take() {
-                self.queue.push_back(*boxed_left);
-            }
-            if let Some(boxed_right) = current_node.right.take() {
-                self.queue.push_back(*boxed_right);
-            }
-            (current_node.key, current_node.value)
-        })
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
 }
 
-// O(n) time to pick one of n items.
-fn cheeze_it<T>(source: impl Iterator<Item = T>, rng: &mut ThreadRng) -> Option<T> {
-    let mut ret: Option<T> = None;
-    let mut count: usize = 0;
-    for item in source {
-        count += 1;
-        if rng.gen_range(0, count) == 0 {
-            ret = Some(item);
-        }
+impl<K, Q, V, S> Index<&Q> for RandomTree<K, V, S>
+where
+    K: Ord + Borrow<Q>,
+    Q: Ord + ?Sized,
+    S: Summary<K, V>,
+{
+    type Output = V;
+
+    // Panics if key is absent.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+// Writes the new weight of the value back up the tree on Drop.
+// Holds the path rather than a reference to the node, since the nodes
+// above it need updating too. Like any other weight, an invalid one left
+// in the value counts as 0.
+pub struct WeightedMut<'a, K, V>
+where
+    V: Weight,
+{
+    root: &'a mut Node<K, V, TotalWeight>,
+    path: Vec<Ordering>,
+}
+
+impl<'a, K, V> Deref for WeightedMut<'a, K, V>
+where
+    V: Weight,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        &self.root.follow(&self.path).value
+    }
+}
+
+impl<'a, K, V> DerefMut for WeightedMut<'a, K, V>
+where
+    V: Weight,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.root.follow_mut(&self.path).value
+    }
+}
+
+impl<'a, K, V> Drop for WeightedMut<'a, K, V>
+where
+    V: Weight,
+{
+    fn drop(&mut self) {
+        self.root.modify(&self.path, |_| ());
     }
-    ret
 }
 
 #[cfg(test)]
@@ -335,33 +1490,239 @@ mod tests {
         for (i, s) in table.iter() {
             assert_eq!(tree.get(i).map(|out| out.as_str()), Some(*s));
         }
-    }
+    }
+
+    #[test]
+    fn insert_replacement() {
+        let mut tree = RandomTree::<i32, &str>::new();
+        assert_eq!(tree.insert(100, "Original"), None);
+        assert_eq!(tree.insert(100, "Replacement"), Some("Original"));
+        assert_eq!(tree.get(&100), Some(&"Replacement"));
+    }
+
+    #[test]
+    fn birthday_paradox() {
+        let mut bday_set = RandomTree::new();
+        let mut rng = thread_rng();
+
+        assert!(bday_set.random(&mut rng).is_none());
+
+        let mut days = (1..366).collect::<Vec<_>>();
+        days.shuffle(&mut rng);
+        for day in days {
+            bday_set.insert(day, ());
+        }
+        let mut selection = (0..27)
+            .map(|_| bday_set.random(&mut rng).unwrap().0)
+            .collect::<Vec<_>>();
+        selection.sort_unstable();
+        println!("{:?}", selection);
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let mut tree = RandomTree::new();
+        for i in 0..1_000 {
+            tree.insert(i, ());
+        }
+
+        let draw = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..100)
+                .map(|_| *tree.random(&mut rng).unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let from_tree = reservoir::choose(tree.iter(), &mut rng);
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(reservoir::choose(tree.iter(), &mut rng), from_tree);
+    }
+
+    #[test]
+    fn random_weighted() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut tree = RandomTree::<char, f64, TotalWeight>::with_summary();
+        assert!(tree.random_weighted(&mut rng).is_none());
+
+        tree.insert('a', 0.0);
+        assert!(tree.random_weighted(&mut rng).is_none());
+
+        tree.insert('b', 1.0);
+        tree.insert('c', 3.0);
+        tree.insert('d', 1.0);
+        tree.insert('e', 2.0);
+        tree.remove(&'e');
+        assert_eq!(tree.total_weight(), 5.0);
+        assert_eq!(tree.weight(&'c'), Some(3.0));
+        assert_eq!(tree.weight(&'e'), None);
+
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            let (k, _) = tree.random_weighted(&mut rng).unwrap();
+            counts[(*k as u8 - b'a') as usize] += 1;
+        }
+        assert_eq!(counts[0], 0);
+        assert!((1_800..2_200).contains(&counts[1]));
+        assert!((5_700..6_300).contains(&counts[2]));
+        assert!((1_800..2_200).contains(&counts[3]));
+    }
+
+    #[test]
+    fn weights_follow_values() {
+        let mut tree = RandomTree::<i32, f64, TotalWeight>::with_summary();
+        for i in 0..1_000 {
+            tree.insert(i, i as f64);
+        }
+        assert_eq!(tree.summary(), TotalWeight(499_500.0));
+
+        *tree.get_mut(&10).unwrap() = 0.5;
+        assert_eq!(tree.get(&10), Some(&0.5));
+        assert_eq!(tree.insert(20, 0.0), Some(20.0));
+        for i in 500..1_000 {
+            tree.remove(&i);
+        }
+        assert_eq!(tree.summary(), TotalWeight(124_750.0 - 10.0 + 0.5 - 20.0));
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_summary());
+        assert!(root.validate_balance());
+
+        // An entry zeroed through get_mut or insert is never drawn again.
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut tree = RandomTree::<i32, f64, TotalWeight>::with_summary();
+        tree.insert(1, 5.0);
+        tree.insert(2, 1.0);
+        *tree.get_mut(&1).unwrap() = 0.0;
+        assert_eq!(tree.summary(), TotalWeight(1.0));
+        for _ in 0..100 {
+            assert_eq!(tree.random_by_measure(&mut rng), Some((&2, &1.0)));
+        }
+        assert_eq!(tree.insert(2, 0.0), Some(1.0));
+        assert!(tree.random_by_measure(&mut rng).is_none());
+
+        // So is one whose weight is made invalid, and the others still add up.
+        tree.insert(3, 2.0);
+        *tree.get_mut(&2).unwrap() = f64::NAN;
+        *tree.get_mut(&1).unwrap() = -1.0;
+        assert_eq!(tree.summary(), TotalWeight(2.0));
+        assert_eq!(tree.weight(&2), Some(0.0));
+        for _ in 0..100 {
+            assert_eq!(tree.random_weighted(&mut rng), Some((&3, &2.0)));
+        }
+        assert!(tree.root.as_ref().unwrap().validate_summary());
 
-    #[test]
-    fn insert_replacement() {
-        let mut tree = RandomTree::<i32, &str>::new();
-        assert_eq!(tree.insert(100, "Original"), None);
-        assert_eq!(tree.insert(100, "Replacement"), Some("Original"));
-        assert_eq!(tree.get(&100), Some(&"Replacement"));
+        // Explicit weights stay put whatever happens to the values.
+        let mut tree = RandomTree::<i32, Weighted<&str>, TotalWeight>::with_summary();
+        tree.insert(1, Weighted::new("a", 2.0));
+        tree.insert(2, Weighted::new("b", 0.0));
+        tree.get_mut(&1).unwrap().value = "c";
+        assert_eq!(tree.weight(&1), Some(2.0));
+        tree.get_mut(&1).unwrap().weight = 3.0;
+        assert_eq!(tree.total_weight(), 3.0);
+        assert_eq!(tree.random_weighted(&mut rng).unwrap().1.value, "c");
+        assert!(tree.root.as_ref().unwrap().validate_summary());
     }
 
     #[test]
-    fn birthday_paradox() {
+    fn birthday_sample() {
         let mut bday_set = RandomTree::new();
         let mut rng = thread_rng();
 
-        assert!(bday_set.random(&mut rng).is_none());
+        assert!(bday_set.sample(27, &mut rng).is_empty());
 
         let mut days = (1..366).collect::<Vec<_>>();
         days.shuffle(&mut rng);
         for day in days {
             bday_set.insert(day, ());
         }
-        let mut selection = (0..27)
-            .map(|_| bday_set.random(&mut rng).unwrap().0)
+
+        let mut selection = bday_set
+            .sample(27, &mut rng)
+            .into_iter()
+            .map(|(day, _)| *day)
             .collect::<Vec<_>>();
         selection.sort_unstable();
-        println!("{:?}", selection);
+        selection.dedup();
+        assert_eq!(selection.len(), 27);
+        assert!(selection.iter().all(|day| bday_set.has(day)));
+
+        let sorted = bday_set.sample_sorted(27, &mut rng);
+        assert_eq!(sorted.len(), 27);
+        assert!(sorted.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let everything = bday_set.sample_sorted(1_000, &mut rng);
+        assert!(everything.into_iter().map(|(day, _)| *day).eq(1..366));
+    }
+
+    #[test]
+    fn pop_random() {
+        let mut rng = StdRng::seed_from_u64(16);
+        let mut jobs = (0..1_000).map(|k| (k, k * 2)).collect::<RandomTree<_, _>>();
+
+        let (key, value) = jobs.pop_random(&mut rng).unwrap();
+        assert_eq!(value, key * 2);
+        assert!(!jobs.has(&key));
+        assert_eq!(jobs.len(), 999);
+
+        let mut drawn = jobs.pop_random_k(500, &mut rng);
+        assert_eq!(drawn.len(), 500);
+        assert_eq!(jobs.len(), 499);
+        assert!(jobs.validate());
+        drawn.extend(jobs.pop_random_k(1_000, &mut rng));
+        drawn.push((key, value));
+        assert!(jobs.is_empty());
+        assert!(jobs.pop_random(&mut rng).is_none());
+        drawn.sort_unstable();
+        assert!(drawn.into_iter().eq((0..1_000).map(|k| (k, k * 2))));
+
+        // Every entry is equally likely to go first.
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            let mut pool = (0..4).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+            counts[pool.pop_random(&mut rng).unwrap().0] += 1;
+        }
+        assert!(counts.iter().all(|count| (2_300..2_700).contains(count)));
+    }
+
+    #[test]
+    fn random_where() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let tree = (0..10_000)
+            .map(|k| (k, k % 3))
+            .collect::<RandomTree<_, _>>();
+
+        let (entry, strategy) = tree.random_where(|_, v| *v != 0, &mut rng);
+        assert_ne!(entry.unwrap().1, &0);
+        assert_eq!(strategy, Strategy::Rejection);
+
+        let (entry, strategy) = tree.random_where(|k, _| k % 1_000 == 7, &mut rng);
+        assert_eq!(entry.unwrap().0 % 1_000, 7);
+        assert_eq!(strategy, Strategy::Scan);
+
+        assert_eq!(
+            tree.random_where(|k, _| *k >= 10_000, &mut rng),
+            (None, Strategy::Scan)
+        );
+        let empty = RandomTree::<u8, ()>::new();
+        assert_eq!(
+            empty.random_where(|_, _| true, &mut rng),
+            (None, Strategy::Scan)
+        );
+
+        // Both strategies pick uniformly: a quarter of 8 entries match in
+        // the first tree, 4 of 400 in the second.
+        for len in [8, 400].iter() {
+            let tree = (0..*len).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+            let step = len / 4;
+            let mut counts = [0; 4];
+            for _ in 0..10_000 {
+                let (entry, _) = tree.random_where(|k, _| k % step == 0, &mut rng);
+                counts[entry.unwrap().0 / step] += 1;
+            }
+            assert!(counts.iter().all(|count| (2_300..2_700).contains(count)));
+        }
     }
 
     #[test]
@@ -369,7 +1730,7 @@ mod tests {
         let mut bday_set = RandomTree::new();
         let mut rng = thread_rng();
 
-        assert!(cheeze_it(bday_set.iter(), &mut rng).is_none());
+        assert!(reservoir::choose(bday_set.iter(), &mut rng).is_none());
 
         let mut days = (1..366).collect::<Vec<_>>();
         days.shuffle(&mut rng);
@@ -377,7 +1738,7 @@ mod tests {
             bday_set.insert(day, ());
         }
         let mut selection = (0..27)
-            .map(|_| cheeze_it(bday_set.iter(), &mut rng).unwrap().0)
+            .map(|_| reservoir::choose(bday_set.iter(), &mut rng).unwrap().0)
             .copied()
             .collect::<Vec<_>>();
         selection.sort_unstable();
@@ -424,6 +1785,359 @@ mod tests {
         assert_eq!(original[..], recovered[..]);
     }
 
+    #[test]
+    fn sorted_iter() {
+        let mut rng = thread_rng();
+        let mut original = (0..1_000).collect::<Vec<_>>();
+        original.shuffle(&mut rng);
+
+        let mut tree = RandomTree::new();
+        for i in original.iter() {
+            tree.insert(*i, i * 2);
+        }
+
+        assert!(tree.keys().copied().eq(0..1_000));
+        assert!(tree.keys().rev().copied().eq((0..1_000).rev()));
+        assert!(tree.values().copied().eq((0..1_000).map(|i| i * 2)));
+        assert_eq!(tree.iter().len(), 1_000);
+        assert_eq!(
+            tree.iter_bfs().next().map(|(k, _)| k),
+            tree.root.as_ref().map(|root| &root.key)
+        );
+        assert_eq!(tree.iter_bfs().count(), 1_000);
+
+        // Meeting in the middle.
+        let mut both_ends = tree.iter();
+        for i in 0..500 {
+            assert_eq!(both_ends.next(), Some((&i, &(i * 2))));
+            assert_eq!(both_ends.len(), 999 - 2 * i);
+            assert_eq!(both_ends.next_back(), Some((&(999 - i), &((999 - i) * 2))));
+        }
+        assert_eq!(both_ends.len(), 0);
+        assert_eq!(both_ends.next(), None);
+        assert_eq!(both_ends.next_back(), None);
+
+        for v in tree.values_mut() {
+            *v += 1;
+        }
+        for (k, v) in tree.iter_mut().rev().take(10) {
+            *v = *k;
+        }
+        assert_eq!(tree.get(&0), Some(&1));
+        assert_eq!(tree.get(&989), Some(&1_979));
+        assert_eq!(tree.get(&990), Some(&990));
+
+        assert!(tree.into_iter().map(|(k, _)| k).eq(0..1_000));
+    }
+
+    #[test]
+    fn range_iter() {
+        let mut tree = RandomTree::new();
+        for i in (0..1_000).step_by(2) {
+            tree.insert(i, ());
+        }
+
+        assert!(tree.range(10..20).map(|(k, _)| *k).eq((10..20).step_by(2)));
+        assert!(tree
+            .range(11..=20)
+            .map(|(k, _)| *k)
+            .eq((12..=20).step_by(2)));
+        assert!(tree.range(..5).rev().map(|(k, _)| *k).eq(vec![4, 2, 0]));
+        assert!(tree.range(995..).map(|(k, _)| *k).eq(vec![996, 998]));
+        assert_eq!(tree.range(..).len(), 500);
+        assert_eq!(tree.range(1_000..).len(), 0);
+        assert_eq!(tree.range(3..4).next(), None);
+        assert_eq!(
+            tree.range((Bound::Excluded(20), Bound::Excluded(10)))
+                .next(),
+            None
+        );
+    }
+
+    #[test]
+    fn entry() {
+        let mut counter = RandomTree::new();
+        let words = "the quick brown fox jumps over the lazy dog the end";
+        for word in words.split(' ') {
+            *counter.entry(word).or_insert(0) += 1;
+        }
+        assert_eq!(counter.get(&"the"), Some(&3));
+        assert_eq!(counter.get(&"fox"), Some(&1));
+        assert_eq!(counter.iter().len(), 9);
+
+        counter.entry("fox").and_modify(|n| *n *= 10).or_insert(0);
+        counter.entry("cat").and_modify(|n| *n *= 10).or_insert(7);
+        assert_eq!(counter.get(&"fox"), Some(&10));
+        assert_eq!(counter.get(&"cat"), Some(&7));
+        assert_eq!(*counter.entry("cow").or_insert_with(|| 5), 5);
+        assert_eq!(*counter.entry("cow").or_insert_with(|| unreachable!()), 5);
+
+        match counter.entry("dog") {
+            Entry::Occupied(mut occupied) => {
+                assert_eq!(occupied.key(), &"dog");
+                assert_eq!(occupied.insert(2), 1);
+            }
+            Entry::Vacant(_) => panic!("dog should be there"),
+        }
+        match counter.entry("emu") {
+            Entry::Vacant(vacant) => assert_eq!(vacant.key(), &"emu"),
+            Entry::Occupied(_) => panic!("emu should not be there"),
+        }
+        assert_eq!(counter.get(&"dog"), Some(&2));
+        assert!(!counter.has(&"emu"));
+    }
+
+    #[test]
+    fn entry_keeps_balance() {
+        let mut tree = RandomTree::<i32, u8>::new();
+        for i in 0..10_000 {
+            *tree.entry(i / 2).or_default() += 1;
+        }
+        assert!(tree.values().all(|count| *count == 2));
+        assert!(tree.keys().copied().eq(0..5_000));
+
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_children());
+        assert!(root.validate_balance());
+    }
+
+    #[test]
+    fn vacant_entries_stay_balanced() {
+        let mut rng = thread_rng();
+        let mut keys = (0..2_000).collect::<Vec<_>>();
+        let reversed = keys.iter().rev().copied().collect::<Vec<_>>();
+        let ascending = keys.clone();
+        keys.shuffle(&mut rng);
+        for order in [ascending, reversed, keys].iter() {
+            let mut tree = RandomTree::new();
+            for &key in order.iter() {
+                *tree.entry(key).or_insert(0) += key;
+                let root = tree.root.as_ref().unwrap();
+                assert!(root.validate_balance());
+            }
+            assert!(tree.validate());
+            assert!(tree.iter().all(|(key, value)| key == value));
+            assert!(tree.keys().copied().eq(0..2_000));
+
+            // Replacing rebalances on the way down too, as if inserting.
+            for &key in order.iter() {
+                assert_eq!(tree.insert(key, key + 1), Some(key));
+                assert!(tree.root.as_ref().unwrap().validate_balance());
+            }
+            assert!(tree.validate());
+        }
+    }
+
+    #[test]
+    fn collection_traits() {
+        let mut tree = vec![(3, "c"), (1, "a")]
+            .into_iter()
+            .collect::<RandomTree<_, _>>();
+        tree.extend(vec![(2, "b"), (3, "C")]);
+        assert_eq!(tree.len(), 3);
+        assert!(!tree.is_empty());
+        assert_eq!(tree[&3], "C");
+
+        for (_, value) in &mut tree {
+            *value = "x";
+        }
+        assert!((&tree).into_iter().all(|(_, value)| *value == "x"));
+        assert!(tree.into_iter().map(|(key, _)| key).eq(1..=3));
+        assert!(RandomTree::<u8, ()>::default().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_missing() {
+        let tree = RandomTree::<u8, u8>::new();
+        let _ = tree[&0];
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let mut tree = RandomTree::new();
+        for word in ["alpha", "beta", "gamma"].iter() {
+            tree.insert(word.to_string(), word.len());
+        }
+
+        assert_eq!(tree.get("beta"), Some(&4));
+        assert!(tree.has("gamma"));
+        assert!(!tree.has("delta"));
+        *tree.get_mut("alpha").unwrap() = 0;
+        assert_eq!(tree["alpha"], 0);
+        assert_eq!(tree.rank("beta"), Ok(1));
+        assert_eq!(tree.remove("gamma"), Some(5));
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn values_without_debug() {
+        struct Handle(u32);
+
+        let mut rng = thread_rng();
+        let mut tree = RandomTree::<u32, Box<dyn Fn(u32) -> u32>>::new();
+        tree.insert(1, Box::new(|x| x + 1));
+        tree.insert(2, Box::new(|x| x + 2));
+        assert_eq!(tree.get(&2).map(|f| f(10)), Some(12));
+        let (key, f) = tree.random(&mut rng).unwrap();
+        assert_eq!(f(0), *key);
+
+        let mut handles = RandomTree::new();
+        handles.insert("socket", Handle(3));
+        assert_eq!(handles.values().map(|handle| handle.0).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn bulk_build() {
+        let tree = RandomTree::from_sorted_iter((0..100_000).map(|i| (i, i * 3)));
+        assert_eq!(tree.len(), 100_000);
+        assert!(tree
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..100_000).map(|i| (i, i * 3))));
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_children());
+        assert!(root.validate_balance());
+        assert_eq!(root.height(), 17);
+
+        let tree = RandomTree::from_sorted_iter(vec![(1, 'a'), (1, 'b'), (2, 'c')]);
+        assert!(tree.iter().eq(vec![(&1, &'b'), (&2, &'c')]));
+        assert!(RandomTree::<u8, ()>::from_sorted_iter(None).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "keys are not sorted")]
+    fn bulk_build_unsorted() {
+        RandomTree::from_sorted_iter(vec![(2, ()), (1, ())]);
+    }
+
+    #[test]
+    fn collect_unsorted() {
+        let mut rng = thread_rng();
+        let mut keys = (0..1_000).chain(0..1_000).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+
+        let tree = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (*k, i))
+            .collect::<RandomTree<_, _>>();
+        assert!(tree.keys().copied().eq(0..1_000));
+        for (k, i) in tree.iter() {
+            assert_eq!(keys.iter().rposition(|other| other == k), Some(*i));
+        }
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_children());
+        assert!(root.validate_balance());
+    }
+
+    impl<K, V, S> RandomTree<K, V, S>
+    where
+        S: Summary<K, V>,
+    {
+        fn validate(&self) -> bool {
+            self.root
+                .as_ref()
+                .is_none_or(|root| root.validate_children() && root.validate_balance())
+        }
+    }
+
+    #[test]
+    fn split_off() {
+        let mut rng = thread_rng();
+        for len in 0..40 {
+            let mut keys = (0..len).map(|k| k * 2).collect::<Vec<_>>();
+            keys.shuffle(&mut rng);
+            for at in -1..=2 * len {
+                let mut tree = RandomTree::new();
+                for k in keys.iter() {
+                    tree.insert(*k, ());
+                }
+
+                let right = tree.split_off(&at);
+                assert!(tree.keys().all(|k| *k < at));
+                assert!(right.keys().all(|k| *k >= at));
+                assert_eq!(tree.len() + right.len(), len as usize);
+                assert!(tree.validate());
+                assert!(right.validate());
+            }
+        }
+    }
+
+    #[test]
+    fn split_and_join() {
+        let mut rng = thread_rng();
+        let mut tree = (0..100_000).map(|k| (k, k)).collect::<RandomTree<_, _>>();
+        for _ in 0..100 {
+            let at = rng.gen_range(-10, 100_010);
+            let right = tree.split_off(&at);
+            assert!(tree.validate());
+            assert!(right.validate());
+            tree = RandomTree::join(tree, right);
+            assert!(tree.validate());
+        }
+        assert!(tree
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..100_000).map(|k| (k, k))));
+
+        // Joining very different sizes.
+        let big = tree.split_off(&10);
+        let small = std::mem::take(&mut tree);
+        let joined = RandomTree::join(small, big);
+        assert!(joined.validate());
+        assert_eq!(joined.len(), 100_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "key ranges overlap")]
+    fn join_overlapping() {
+        let left = (0..10).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+        let right = (5..15).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+        RandomTree::join(left, right);
+    }
+
+    #[test]
+    fn append() {
+        let mut low = (0..1_000).map(|k| (k, 'l')).collect::<RandomTree<_, _>>();
+        let mut high = (1_000..1_500)
+            .map(|k| (k, 'h'))
+            .collect::<RandomTree<_, _>>();
+        high.append(&mut low);
+        assert!(low.is_empty());
+        assert!(high.keys().copied().eq(0..1_500));
+        assert!(high.validate());
+
+        let mut overlapping = (1_400..2_000)
+            .map(|k| (k, 'o'))
+            .collect::<RandomTree<_, _>>();
+        high.append(&mut overlapping);
+        assert!(overlapping.is_empty());
+        assert!(high.keys().copied().eq(0..2_000));
+        assert_eq!(high.get(&1_399), Some(&'h'));
+        assert_eq!(high.get(&1_400), Some(&'o'));
+        assert!(high.validate());
+
+        high.append(&mut RandomTree::new());
+        assert_eq!(high.len(), 2_000);
+
+        // Weights come along with their entries.
+        let mut tree = RandomTree::<i32, Weighted<()>, TotalWeight>::with_summary();
+        tree.insert(1, Weighted::new((), 5.0));
+        tree.insert(3, Weighted::new((), 7.0));
+        let mut other = RandomTree::with_summary();
+        other.insert(2, Weighted::new((), 9.0));
+        other.insert(3, Weighted::new((), 1.0));
+        tree.append(&mut other);
+        assert_eq!(tree.weight(&1), Some(5.0));
+        assert_eq!(tree.weight(&2), Some(9.0));
+        assert_eq!(tree.weight(&3), Some(1.0));
+        assert_eq!(tree.total_weight(), 15.0);
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_children());
+        assert!(root.validate_summary());
+    }
+
     #[test]
     fn has() {
         let mut map = RandomTree::new();
@@ -453,14 +2167,73 @@ mod tests {
         assert_eq!(counter.get(&2), Some(&33));
     }
 
-    impl<K, V> Node<K, V>
+    impl<K, V, S> Node<K, V, S>
     where
-        K: fmt::Debug,
-        V: fmt::Debug,
+        S: Summary<K, V>,
     {
+        fn iter_bfs(&self) -> IterBfs<'_, K, V, S> {
+            IterBfs::new(Some(self))
+        }
+
         fn validate_children(&self) -> bool {
-            let actual_descendants = self.iter().map(|_| 1).sum::<usize>() - 1;
-            Some(actual_descendants) == self.descendants
+            let actual_size = self.iter_bfs().count();
+            actual_size == self.size()
+        }
+
+        fn validate_balance(&self) -> bool {
+            self.left_balance_weight() <= DELTA * self.right_balance_weight()
+                && self.right_balance_weight() <= DELTA * self.left_balance_weight()
+                && self
+                    .left
+                    .as_ref()
+                    .is_none_or(|left| left.validate_balance())
+                && self
+                    .right
+                    .as_ref()
+                    .is_none_or(|right| right.validate_balance())
+        }
+
+        fn validate_summary(&self) -> bool
+        where
+            S: PartialEq,
+        {
+            let mut expected = S::empty();
+            for (key, value) in Iter::new(Some(self)) {
+                expected = expected.combine(&S::of(key, value));
+            }
+            self.summary == expected
+                && self
+                    .left
+                    .as_ref()
+                    .is_none_or(|left| left.validate_summary())
+                && self
+                    .right
+                    .as_ref()
+                    .is_none_or(|right| right.validate_summary())
+        }
+
+        fn height(&self) -> usize {
+            let left = self.left.as_ref().map_or(0, |left| left.height());
+            let right = self.right.as_ref().map_or(0, |right| right.height());
+            1 + left.max(right)
+        }
+    }
+
+    #[test]
+    fn sorted_insert_is_balanced() {
+        let mut ascending = RandomTree::new();
+        let mut descending = RandomTree::new();
+        for i in 0..100_000 {
+            ascending.insert(i, ());
+            descending.insert(-i, ());
+        }
+
+        for tree in [ascending, descending].iter() {
+            let root = tree.root.as_ref().unwrap();
+            assert_eq!(root.size(), 100_000);
+            assert!(root.validate_balance());
+            // log_{4/3}(100_001) is just over 40.
+            assert!(root.height() <= 41);
         }
     }
 
@@ -474,24 +2247,9 @@ mod tests {
         }
 
         for (k, _) in tree.iter() {
-            assert!(tree.find(&k).unwrap().validate_children());
+            assert!(tree.find(k).unwrap().validate_children());
         }
-    }
-
-    #[test]
-    fn take_branch() {
-        let mut tree = RandomTree::new();
-        tree.insert(4, ());
-        tree.insert(2, ());
-        tree.insert(1, ());
-        tree.insert(3, ());
-        tree.insert(6, ());
-        tree.insert(5, ());
-        tree.insert(7, ());
-        dbg!(&tree);
-        let branch = tree.take_branch(&2);
-        dbg!(&branch);
-        dbg!(&tree);
+        assert!(tree.root.as_ref().unwrap().validate_balance());
     }
 
     #[test]
@@ -502,7 +2260,8 @@ mod tests {
         tree.insert(1, ());
         tree.insert(3, ());
 
-        tree.remove(&2);
+        assert_eq!(tree.remove(&2), Some(()));
+        assert_eq!(tree.remove(&2), None);
 
         assert!(tree.has(&4));
         assert!(tree.has(&1));
@@ -512,4 +2271,329 @@ mod tests {
             assert!(tree.find(k).unwrap().validate_children());
         }
     }
+
+    #[test]
+    fn order_statistics() {
+        let mut rng = thread_rng();
+        let mut keys = (0..1_000).map(|i| i * 10).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+
+        let mut tree = RandomTree::new();
+        assert_eq!(tree.select(0), None);
+        assert_eq!(tree.rank(&5), Err(0));
+        for k in keys {
+            tree.insert(k, k + 1);
+        }
+
+        for i in 0..1_000 {
+            assert_eq!(tree.select(i), Some((&(i * 10), &(i * 10 + 1))));
+            assert_eq!(tree.rank(&(i * 10)), Ok(i));
+            assert_eq!(tree.rank(&(i * 10 + 5)), Err(i + 1));
+        }
+        assert_eq!(tree.select(1_000), None);
+        assert_eq!(tree.rank(&1), Err(1));
+
+        *tree.get_index_mut(950).unwrap().1 = 0;
+        assert_eq!(tree.get(&9_500), Some(&0));
+        assert!(tree.get_index_mut(1_000).is_none());
+
+        assert_eq!(tree.remove_index(1_000), None);
+        assert_eq!(tree.remove_index(500), Some((5_000, 5_001)));
+        assert_eq!(tree.remove_index(0), Some((0, 1)));
+        assert_eq!(tree.select(0), Some((&10, &11)));
+        assert_eq!(tree.select(499), Some((&5_010, &5_011)));
+        assert_eq!(tree.rank(&5_000), Err(499));
+
+        let root = tree.root.as_ref().unwrap();
+        assert!(root.validate_children());
+        assert!(root.validate_balance());
+    }
+
+    #[test]
+    fn ranges() {
+        let mut rng = thread_rng();
+        let mut tree = RandomTree::new();
+        assert_eq!(tree.count_range(..), 0);
+        assert!(tree.random_in_range(.., &mut rng).is_none());
+
+        for minute in (0..24 * 60).step_by(7) {
+            tree.insert(minute, ());
+        }
+        assert_eq!(tree.count_range(..), 206);
+        assert_eq!(tree.count_range(60..120), 9);
+        assert_eq!(tree.count_range(63..=119), 9);
+        assert_eq!(tree.count_range(63..119), 8);
+        assert_eq!(
+            tree.count_range((Bound::Excluded(63), Bound::Included(119))),
+            8
+        );
+        assert_eq!(tree.count_range(1_435..), 1);
+        assert_eq!(tree.count_range(..0), 0);
+        assert_eq!(
+            tree.count_range((Bound::Included(120), Bound::Excluded(60))),
+            0
+        );
+
+        for _ in 0..1_000 {
+            let (minute, _) = tree.random_in_range(60..120, &mut rng).unwrap();
+            assert!((60..120).contains(minute));
+            assert_eq!(minute % 7, 0);
+        }
+        assert_eq!(tree.random_in_range(1..7, &mut rng), None);
+        assert_eq!(tree.random_in_range(..=0, &mut rng), Some((&0, &())));
+    }
+
+    #[test]
+    fn remove_keeps_balance() {
+        let mut rng = thread_rng();
+        let mut tree = RandomTree::new();
+        for i in 0..10_000 {
+            tree.insert(i, i * 2);
+        }
+
+        let mut keys = (0..10_000).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+        let (removed, kept) = keys.split_at(7_500);
+        for k in removed {
+            assert_eq!(tree.remove(k), Some(k * 2));
+        }
+
+        for k in removed {
+            assert!(!tree.has(k));
+        }
+        for k in kept {
+            assert_eq!(tree.get(k), Some(&(k * 2)));
+            assert!(tree.find(k).unwrap().validate_children());
+        }
+        assert!(tree.root.as_ref().unwrap().validate_balance());
+    }
+
+    #[test]
+    fn range_sums() {
+        let mut rng = thread_rng();
+        let mut tree = RandomTree::<i32, i64, Sum<i64>>::with_summary();
+        let mut plain = std::collections::BTreeMap::new();
+        for _ in 0..2_000 {
+            let (key, value) = (rng.gen_range(0, 1_000), rng.gen_range(-100, 100));
+            assert_eq!(tree.insert(key, value), plain.insert(key, value));
+        }
+        for _ in 0..500 {
+            let key = rng.gen_range(0, 1_000);
+            assert_eq!(tree.remove(&key), plain.remove(&key));
+        }
+        assert!(tree.validate());
+        assert!(tree.root.as_ref().unwrap().validate_summary());
+
+        assert_eq!(tree.summary(), Sum(plain.values().sum()));
+        assert_eq!(tree.summarize(..), tree.summary());
+        assert_eq!(tree.summarize(500..500), Sum(0));
+        for _ in 0..1_000 {
+            let start = rng.gen_range(-10, 1_010);
+            let end = rng.gen_range(start, 1_020);
+            let expected = plain.range(start..end).map(|(_, v)| v).sum();
+            assert_eq!(tree.summarize(start..end), Sum(expected));
+        }
+    }
+
+    #[test]
+    fn summaries_follow_updates() {
+        let mut tree = (0..1_000)
+            .map(|k| (k, k))
+            .collect::<RandomTree<i32, i32, (Max<i32>, Min<i32>)>>();
+        assert_eq!(tree.summarize(100..=200), (Max(Some(200)), Min(Some(100))));
+
+        assert_eq!(
+            tree.update(&150, |v| std::mem::replace(v, 5_000)),
+            Some(150)
+        );
+        assert_eq!(tree.update(&5_000, |v| *v = 0), None);
+        assert_eq!(
+            tree.summarize(100..=200),
+            (Max(Some(5_000)), Min(Some(100)))
+        );
+        assert_eq!(tree.summarize(..150), (Max(Some(149)), Min(Some(0))));
+
+        let right = tree.split_off(&500);
+        assert_eq!(right.summary(), (Max(Some(999)), Min(Some(500))));
+        assert_eq!(tree.summary(), (Max(Some(5_000)), Min(Some(0))));
+        let tree = RandomTree::join(right, tree.split_off(&1_000));
+        assert!(tree.root.as_ref().unwrap().validate_summary());
+
+        let empty = RandomTree::<i32, i32, Max<i32>>::default();
+        assert_eq!(empty.summary(), Max(None));
+        assert_eq!(empty.summarize(..), Max(None));
+    }
+
+    #[test]
+    fn random_by_measure() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut tree = RandomTree::<char, f64, TotalWeight>::with_summary();
+        assert!(tree.random_by_measure(&mut rng).is_none());
+
+        tree.insert('a', 0.0);
+        assert!(tree.random_by_measure(&mut rng).is_none());
+
+        tree.insert('b', 1.0);
+        tree.insert('c', 5.0);
+        tree.update(&'c', |weight| *weight = 3.0);
+        tree.insert('d', 1.0);
+        tree.insert('e', 1.0);
+        tree.remove(&'e');
+        assert_eq!(tree.summary(), TotalWeight(5.0));
+
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            let (k, _) = tree.random_by_measure(&mut rng).unwrap();
+            counts[(*k as u8 - b'a') as usize] += 1;
+        }
+        assert_eq!(counts[0], 0);
+        assert!((1_800..2_200).contains(&counts[1]));
+        assert!((5_700..6_300).contains(&counts[2]));
+        assert!((1_800..2_200).contains(&counts[3]));
+
+        // Counting makes every entry weigh the same.
+        let tree = (0..4).map(|k| (k, ())).collect::<RandomTree<_, _, Count>>();
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            counts[*tree.random_by_measure(&mut rng).unwrap().0] += 1;
+        }
+        assert!(counts.iter().all(|count| (2_300..2_700).contains(count)));
+    }
+
+    #[test]
+    fn retain() {
+        let mut tree = (0..100_000)
+            .map(|k| (k, k))
+            .collect::<RandomTree<i64, i64, Sum<i64>>>();
+        let mut seen = Vec::new();
+        tree.retain(|k, v| {
+            seen.push(*k);
+            *v *= 2;
+            k % 3 == 0
+        });
+        assert!(seen.into_iter().eq(0..100_000));
+        assert_eq!(tree.len(), 33_334);
+        assert!(tree
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..100_000).step_by(3).map(|k| (k, k * 2))));
+        assert!(tree.validate());
+        assert!(tree.root.as_ref().unwrap().validate_summary());
+
+        // Everything but a few keys at one end.
+        tree.retain(|k, _| *k < 30);
+        assert!(tree.keys().copied().eq((0..30).step_by(3)));
+        assert!(tree.validate());
+        tree.retain(|_, _| false);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut rng = thread_rng();
+        let mut tree = RandomTree::new();
+        for _ in 0..10_000 {
+            tree.insert(rng.gen_range(0, 100_000), ());
+        }
+        let len = tree.len();
+        let odd = tree.extract_if(|k, _| k % 2 == 1);
+        assert!(odd.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(odd.iter().all(|(k, _)| k % 2 == 1));
+        assert!(tree.keys().all(|k| k % 2 == 0));
+        assert_eq!(tree.len() + odd.len(), len);
+        assert!(tree.validate());
+    }
+
+    #[test]
+    fn remove_all() {
+        let mut tree = (0..10_000).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+        // Few keys, so one by one.
+        assert_eq!(tree.remove_all(&[5, 10_000, 7, 5]), 2);
+        assert_eq!(tree.len(), 9_998);
+
+        // Many keys, so in one pass.
+        let keys = (0..20_000).rev().filter(|k| k % 2 == 0).collect::<Vec<_>>();
+        assert_eq!(tree.remove_all(&keys), 5_000);
+        assert!(tree
+            .keys()
+            .copied()
+            .eq((0..10_000).filter(|k| k % 2 == 1 && *k != 5 && *k != 7)));
+        assert!(tree.validate());
+
+        assert_eq!(
+            tree.remove_all(tree.keys().copied().collect::<Vec<_>>().iter()),
+            4_998
+        );
+        assert!(tree.is_empty());
+        assert_eq!(tree.remove_all(&[1]), 0);
+    }
+
+    #[test]
+    fn neighbours() {
+        let mut tree = (0..1_000)
+            .map(|k| (k * 10, k))
+            .collect::<RandomTree<i32, i32>>();
+        for key in (-15..10_015).step_by(7) {
+            let below = tree.keys().rev().find(|k| **k <= key);
+            let above = tree.keys().find(|k| **k >= key);
+            assert_eq!(tree.floor(&key).map(|(k, _)| k), below);
+            assert_eq!(tree.ceiling(&key).map(|(k, _)| k), above);
+            let below = tree.keys().rev().find(|k| **k < key);
+            let above = tree.keys().find(|k| **k > key);
+            assert_eq!(tree.predecessor(&key).map(|(k, _)| k), below);
+            assert_eq!(tree.successor(&key).map(|(k, _)| k), above);
+        }
+
+        assert_eq!(tree.first_key_value(), Some((&0, &0)));
+        assert_eq!(tree.last_key_value(), Some((&9_990, &999)));
+        assert_eq!(tree.pop_first(), Some((0, 0)));
+        assert_eq!(tree.pop_last(), Some((9_990, 999)));
+        assert_eq!(tree.first_key_value(), Some((&10, &1)));
+        assert_eq!(tree.len(), 998);
+        assert!(tree.validate());
+
+        let mut empty = RandomTree::<i32, ()>::new();
+        assert_eq!(empty.floor(&0), None);
+        assert_eq!(empty.successor(&0), None);
+        assert_eq!(empty.first_key_value(), None);
+        assert_eq!(empty.last_key_value(), None);
+        assert_eq!(empty.pop_first(), None);
+        assert_eq!(empty.pop_last(), None);
+    }
+
+    #[test]
+    fn shuffled() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let tree = (0..1_000).map(|k| (k, k * 2)).collect::<RandomTree<_, _>>();
+        let shuffled = tree.shuffled(&mut rng);
+        assert_eq!(shuffled.len(), 1_000);
+        let mut order = shuffled.map(|(k, v)| (*k, *v)).collect::<Vec<_>>();
+        assert!(order.windows(2).any(|pair| pair[0] > pair[1]));
+        order.sort_unstable();
+        assert!(order.into_iter().eq((0..1_000).map(|k| (k, k * 2))));
+        assert_eq!(RandomTree::<u8, ()>::new().shuffled(&mut rng).next(), None);
+
+        // Every order of three entries is equally likely.
+        let tree = (0..3).map(|k| (k, ())).collect::<RandomTree<_, _>>();
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..6_000 {
+            let order = tree.shuffled(&mut rng).map(|(k, _)| *k).collect::<Vec<_>>();
+            *counts.entry(order).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 6);
+        assert!(counts.values().all(|count| (850..1_150).contains(count)));
+
+        // Lazy: a few entries from a big tree don't cost a pass over it.
+        let big = (0..1_000_000)
+            .map(|k| (k, ()))
+            .collect::<RandomTree<_, _>>();
+        let mut firsts = big
+            .shuffled(&mut rng)
+            .take(5)
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        firsts.sort_unstable();
+        firsts.dedup();
+        assert_eq!(firsts.len(), 5);
+    }
 }
@@ -0,0 +1,230 @@
+use crate::{IntoIter, Iter, RandomTree, Sum};
+use rand::Rng;
+use std::borrow::Borrow;
+use std::iter::FromIterator;
+
+// A RandomTree from keys to their multiplicities.
+// The sum of the multiplicities is kept in every node, so that keys can be
+// picked in proportion to them.
+#[derive(Debug)]
+pub struct RandomMultiset<K> {
+    tree: RandomTree<K, usize, Sum<usize>>,
+}
+
+impl<K> RandomMultiset<K>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            tree: RandomTree::with_summary(),
+        }
+    }
+
+    // Returns the multiplicity of key after inserting it.
+    // O(log(n))
+    pub fn insert(&mut self, key: K) -> usize {
+        let count = self.tree.update(&key, |count| {
+            *count += 1;
+            *count
+        });
+        count.unwrap_or_else(|| {
+            self.tree.insert(key, 1);
+            1
+        })
+    }
+
+    // Removes one copy of key. Returns whether there was one.
+    // O(log(n))
+    pub fn remove_one<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let count = self.tree.update(key, |count| {
+            *count -= 1;
+            *count
+        });
+        if count == Some(0) {
+            self.tree.remove(key);
+        }
+        count.is_some()
+    }
+
+    // Removes every copy of key. Returns how many there were.
+    // O(log(n))
+    pub fn remove_all<Q>(&mut self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.remove(key).unwrap_or(0)
+    }
+
+    // The multiplicity of key, 0 if it is absent.
+    // O(log(n))
+    pub fn count<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.get(key).copied().unwrap_or(0)
+    }
+
+    // O(log(n))
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.has(key)
+    }
+
+    // Picks each key with probability proportional to its multiplicity.
+    // Returns None iff the multiset is empty.
+    // O(log(n))
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&K> {
+        self.tree.random_by_measure(rng).map(|(key, _)| key)
+    }
+
+    // Total multiplicity.
+    // O(1)
+    pub fn len(&self) -> usize {
+        self.tree.summary().0
+    }
+
+    // Number of distinct keys.
+    // O(1)
+    pub fn distinct_len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // Iterates over the distinct keys in order, with their multiplicities.
+    pub fn iter(&self) -> Iter<'_, K, usize, Sum<usize>> {
+        self.tree.iter()
+    }
+}
+
+impl<K> Default for RandomMultiset<K>
+where
+    K: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> FromIterator<K> for RandomMultiset<K>
+where
+    K: Ord,
+{
+    // Sorts, then counts the runs of equal keys.
+    // O(n * log(n))
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        let mut keys = iter.into_iter().collect::<Vec<_>>();
+        keys.sort();
+        let mut counted: Vec<(K, usize)> = Vec::new();
+        for key in keys {
+            match counted.last_mut() {
+                Some((last, count)) if *last == key => *count += 1,
+                _ => counted.push((key, 1)),
+            }
+        }
+        Self {
+            tree: counted.into_iter().collect(),
+        }
+    }
+}
+
+impl<K> Extend<K> for RandomMultiset<K>
+where
+    K: Ord,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        for key in iter {
+            self.insert(key);
+        }
+    }
+}
+
+impl<K> IntoIterator for RandomMultiset<K> {
+    type Item = (K, usize);
+    type IntoIter = IntoIter<K, usize, Sum<usize>>;
+
+    // Iterates over the distinct keys in order, with their multiplicities.
+    fn into_iter(self) -> Self::IntoIter {
+        self.tree.into_iter()
+    }
+}
+
+impl<'a, K> IntoIterator for &'a RandomMultiset<K>
+where
+    K: Ord,
+{
+    type Item = (&'a K, &'a usize);
+    type IntoIter = Iter<'a, K, usize, Sum<usize>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn multiset_operations() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let mut bag = RandomMultiset::default();
+        assert!(bag.is_empty());
+        assert_eq!(bag.random(&mut rng), None);
+
+        assert_eq!(bag.insert("a"), 1);
+        assert_eq!(bag.insert("b"), 1);
+        assert_eq!(bag.insert("b"), 2);
+        assert_eq!(bag.insert("b"), 3);
+        assert_eq!(bag.len(), 4);
+        assert_eq!(bag.distinct_len(), 2);
+        assert_eq!(bag.count(&"b"), 3);
+
+        let mut counts = [0; 2];
+        for _ in 0..10_000 {
+            match *bag.random(&mut rng).unwrap() {
+                "a" => counts[0] += 1,
+                _ => counts[1] += 1,
+            }
+        }
+        assert!((2_300..2_700).contains(&counts[0]));
+        assert!((7_300..7_700).contains(&counts[1]));
+
+        assert!(bag.remove_one(&"a"));
+        assert!(!bag.remove_one(&"a"));
+        assert!(!bag.contains(&"a"));
+        assert!(bag.remove_one(&"b"));
+        assert_eq!(bag.len(), 2);
+        assert_eq!(bag.random(&mut rng), Some(&"b"));
+        assert_eq!(bag.remove_all(&"b"), 2);
+        assert!(bag.is_empty());
+        assert_eq!(bag.len(), 0);
+    }
+
+    #[test]
+    fn multiset_iterators() {
+        let mut bag = vec![3, 1, 3, 2, 3]
+            .into_iter()
+            .collect::<RandomMultiset<_>>();
+        bag.extend(vec![1, 4]);
+        assert_eq!(bag.len(), 7);
+        assert!(bag
+            .iter()
+            .map(|(k, c)| (*k, *c))
+            .eq(vec![(1, 2), (2, 1), (3, 3), (4, 1)]));
+        assert!(bag.into_iter().eq(vec![(1, 2), (2, 1), (3, 3), (4, 1)]));
+    }
+}
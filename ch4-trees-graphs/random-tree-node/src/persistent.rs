@@ -0,0 +1,521 @@
+use crate::{
+    iter::{InOrder, Split},
+    DELTA, GAMMA,
+};
+use rand::Rng;
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    ops::Index,
+    sync::Arc,
+};
+
+type Link<K, V> = Option<Arc<PersistentNode<K, V>>>;
+
+struct PersistentNode<K, V> {
+    key: K,
+    value: V,
+    descendants: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+// Same as RandomTree, but never changes once built: insert and remove
+// return a new tree, and leave the old one as it was.
+// The nodes are shared between versions through Arc, and only the nodes on
+// the path from the root to the change are copied, so each new version
+// costs O(log(n)) time and memory. Cloning a tree is O(1).
+// Values can't be borrowed mutably, since other versions may share them,
+// so there is no get_mut, entry or iter_mut.
+pub struct PersistentRandomTree<K, V> {
+    root: Link<K, V>,
+}
+
+impl<K, V> PersistentRandomTree<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self { root: None }
+    }
+
+    // O(1)
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // Returns None iff the tree is empty.
+    // O(log(n))
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<(&K, &V)> {
+        match self.len() {
+            0 => None,
+            len => self.select(rng.gen_range(0, len)),
+        }
+    }
+
+    // A new tree with key associated with value, replacing any old value.
+    // O(log(n))
+    pub fn insert(&self, key: K, value: V) -> Self {
+        Self {
+            root: Some(insert(&self.root, key, value)),
+        }
+    }
+
+    // A new tree without key. Shares everything with self if key is absent.
+    // O(log(n))
+    pub fn remove<Q>(&self, key: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match remove(&self.root, key) {
+            Some(root) => Self { root },
+            None => self.clone(),
+        }
+    }
+
+    // O(log(n))
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let mut current = &self.root;
+        while let Some(node) = current {
+            match key.cmp(node.key.borrow()) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => current = &node.left,
+                Ordering::Greater => current = &node.right,
+            }
+        }
+        None
+    }
+
+    // O(log(n))
+    pub fn has<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    // Returns the i-th smallest entry, counting from 0.
+    // O(log(n))
+    pub fn select(&self, mut i: usize) -> Option<(&K, &V)> {
+        if i >= self.len() {
+            return None;
+        }
+        let mut node = self.root.as_ref().unwrap();
+        loop {
+            let left = size(&node.left);
+            match i.cmp(&left) {
+                Ordering::Less => node = node.left.as_ref().unwrap(),
+                Ordering::Equal => return Some((&node.key, &node.value)),
+                Ordering::Greater => {
+                    i -= left + 1;
+                    node = node.right.as_ref().unwrap();
+                }
+            }
+        }
+    }
+
+    // Iterates in key order.
+    pub fn iter(&self) -> PersistentIter<'_, K, V> {
+        PersistentIter {
+            inner: InOrder::new(self.root.as_deref()),
+        }
+    }
+}
+
+fn size<K, V>(subtree: &Link<K, V>) -> usize {
+    subtree.as_ref().map_or(0, |node| node.descendants + 1)
+}
+
+// Same as Node::subtree_balance_weight.
+fn balance_weight<K, V>(subtree: &Link<K, V>) -> usize {
+    size(subtree) + 1
+}
+
+fn make<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Arc<PersistentNode<K, V>> {
+    Arc::new(PersistentNode {
+        key,
+        value,
+        descendants: size(&left) + size(&right),
+        left,
+        right,
+    })
+}
+
+// Same as Node::balance, building new nodes instead of rotating old ones.
+// Only the nodes that move get copied.
+fn balance<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Arc<PersistentNode<K, V>>
+where
+    K: Clone,
+    V: Clone,
+{
+    let (left_weight, right_weight) = (balance_weight(&left), balance_weight(&right));
+    if right_weight > DELTA * left_weight {
+        let child = right.unwrap();
+        if balance_weight(&child.left) < GAMMA * balance_weight(&child.right) {
+            let new_left = make(key, value, left, child.left.clone());
+            make(
+                child.key.clone(),
+                child.value.clone(),
+                Some(new_left),
+                child.right.clone(),
+            )
+        } else {
+            let grandchild = child.left.as_ref().unwrap();
+            let new_left = make(key, value, left, grandchild.left.clone());
+            let new_right = make(
+                child.key.clone(),
+                child.value.clone(),
+                grandchild.right.clone(),
+                child.right.clone(),
+            );
+            make(
+                grandchild.key.clone(),
+                grandchild.value.clone(),
+                Some(new_left),
+                Some(new_right),
+            )
+        }
+    } else if left_weight > DELTA * right_weight {
+        let child = left.unwrap();
+        if balance_weight(&child.right) < GAMMA * balance_weight(&child.left) {
+            let new_right = make(key, value, child.right.clone(), right);
+            make(
+                child.key.clone(),
+                child.value.clone(),
+                child.left.clone(),
+                Some(new_right),
+            )
+        } else {
+            let grandchild = child.right.as_ref().unwrap();
+            let new_left = make(
+                child.key.clone(),
+                child.value.clone(),
+                child.left.clone(),
+                grandchild.left.clone(),
+            );
+            let new_right = make(key, value, grandchild.right.clone(), right);
+            make(
+                grandchild.key.clone(),
+                grandchild.value.clone(),
+                Some(new_left),
+                Some(new_right),
+            )
+        }
+    } else {
+        make(key, value, left, right)
+    }
+}
+
+fn insert<K, V>(subtree: &Link<K, V>, key: K, value: V) -> Arc<PersistentNode<K, V>>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    let node = match subtree {
+        None => return make(key, value, None, None),
+        Some(node) => node,
+    };
+    match key.cmp(&node.key) {
+        Ordering::Less => {
+            let left = insert(&node.left, key, value);
+            let (node_key, node_value) = (node.key.clone(), node.value.clone());
+            balance(node_key, node_value, Some(left), node.right.clone())
+        }
+        Ordering::Greater => {
+            let right = insert(&node.right, key, value);
+            let (node_key, node_value) = (node.key.clone(), node.value.clone());
+            balance(node_key, node_value, node.left.clone(), Some(right))
+        }
+        Ordering::Equal => make(key, value, node.left.clone(), node.right.clone()),
+    }
+}
+
+// Returns the new subtree, or None if key is absent, so that nothing has to
+// be copied then.
+fn remove<K, V, Q>(subtree: &Link<K, V>, key: &Q) -> Option<Link<K, V>>
+where
+    K: Borrow<Q> + Clone,
+    V: Clone,
+    Q: Ord + ?Sized,
+{
+    let node = subtree.as_ref()?;
+    Some(Some(match key.cmp(node.key.borrow()) {
+        Ordering::Less => {
+            let left = remove(&node.left, key)?;
+            let (node_key, node_value) = (node.key.clone(), node.value.clone());
+            balance(node_key, node_value, left, node.right.clone())
+        }
+        Ordering::Greater => {
+            let right = remove(&node.right, key)?;
+            let (node_key, node_value) = (node.key.clone(), node.value.clone());
+            balance(node_key, node_value, node.left.clone(), right)
+        }
+        Ordering::Equal => match (&node.left, &node.right) {
+            (None, only) | (only, None) => return Some(only.clone()),
+            (Some(_), Some(right)) => {
+                let ((key, value), rest) = pop_min(right);
+                balance(key, value, node.left.clone(), rest)
+            }
+        },
+    }))
+}
+
+// Same as Node::pop_min, copying the path to the minimum.
+fn pop_min<K, V>(node: &Arc<PersistentNode<K, V>>) -> ((K, V), Link<K, V>)
+where
+    K: Clone,
+    V: Clone,
+{
+    match &node.left {
+        None => ((node.key.clone(), node.value.clone()), node.right.clone()),
+        Some(left) => {
+            let (min, rest) = pop_min(left);
+            let node = balance(
+                node.key.clone(),
+                node.value.clone(),
+                rest,
+                node.right.clone(),
+            );
+            (min, Some(node))
+        }
+    }
+}
+
+// Same as Node::build.
+fn build<K, V, I>(entries: &mut I, len: usize) -> Link<K, V>
+where
+    I: Iterator<Item = (K, V)>,
+{
+    if len == 0 {
+        return None;
+    }
+    let left = build(entries, len / 2);
+    let (key, value) = entries.next().unwrap();
+    let right = build(entries, len - len / 2 - 1);
+    Some(make(key, value, left, right))
+}
+
+impl<K, V> Clone for PersistentRandomTree<K, V> {
+    // O(1): the clone shares every node.
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for PersistentRandomTree<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for PersistentRandomTree<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    // Same as for RandomTree: sorts, then builds a perfectly balanced tree.
+    // O(n * log(n)), or O(n) if the entries are already sorted.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut entries = iter.into_iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        crate::dedup_sorted(&mut entries);
+        let len = entries.len();
+        Self {
+            root: build(&mut entries.into_iter(), len),
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a PersistentRandomTree<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = PersistentIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, Q, V> Index<&Q> for PersistentRandomTree<K, V>
+where
+    K: Ord + Clone + Borrow<Q>,
+    V: Clone,
+    Q: Ord + ?Sized,
+{
+    type Output = V;
+
+    // Panics if key is absent.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<'a, K, V> Split for &'a PersistentNode<K, V> {
+    type Entry = (&'a K, &'a V);
+
+    fn size(&self) -> usize {
+        self.descendants + 1
+    }
+
+    fn split(self) -> (Option<Self>, Self::Entry, Option<Self>) {
+        (
+            self.left.as_deref(),
+            (&self.key, &self.value),
+            self.right.as_deref(),
+        )
+    }
+}
+
+pub struct PersistentIter<'a, K, V> {
+    inner: InOrder<&'a PersistentNode<K, V>>,
+}
+
+impl<'a, K, V> Iterator for PersistentIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for PersistentIter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for PersistentIter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for PersistentIter<'a, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    fn validate<K: Ord, V>(subtree: &Link<K, V>) -> bool {
+        subtree.as_ref().is_none_or(|node| {
+            let (left, right) = (balance_weight(&node.left), balance_weight(&node.right));
+            node.descendants == size(&node.left) + size(&node.right)
+                && left <= DELTA * right
+                && right <= DELTA * left
+                && node.left.as_ref().is_none_or(|l| l.key < node.key)
+                && node.right.as_ref().is_none_or(|r| node.key < r.key)
+                && validate(&node.left)
+                && validate(&node.right)
+        })
+    }
+
+    #[test]
+    fn versions() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let mut keys = (0..1_000).collect::<Vec<_>>();
+        keys.shuffle(&mut rng);
+
+        let mut versions = vec![PersistentRandomTree::new()];
+        for k in keys.iter() {
+            let next = versions.last().unwrap().insert(*k, k * 2);
+            versions.push(next);
+        }
+        for (len, version) in versions.iter().enumerate() {
+            assert_eq!(version.len(), len);
+            assert!(validate(&version.root));
+            let mut inserted = keys[..len].to_vec();
+            inserted.sort_unstable();
+            assert!(version.iter().map(|(k, _)| *k).eq(inserted));
+        }
+
+        let full = versions.last().unwrap();
+        let smaller = keys
+            .iter()
+            .take(500)
+            .fold(full.clone(), |tree, k| tree.remove(k));
+        assert_eq!(smaller.len(), 500);
+        assert!(validate(&smaller.root));
+        assert_eq!(full.len(), 1_000);
+        assert!(full
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..1_000).map(|k| (k, k * 2))));
+
+        let replaced = full.insert(5, 0);
+        assert_eq!(replaced[&5], 0);
+        assert_eq!(full[&5], 10);
+        assert!(Arc::ptr_eq(
+            full.remove(&5_000).root.as_ref().unwrap(),
+            full.root.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn random_on_every_version() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let old = (0..4)
+            .map(|k| (k, ()))
+            .collect::<PersistentRandomTree<_, _>>();
+        let new = old.remove(&0).remove(&1);
+        assert!(PersistentRandomTree::<u8, ()>::new()
+            .random(&mut rng)
+            .is_none());
+
+        let (mut old_counts, mut new_counts) = ([0; 4], [0; 4]);
+        for _ in 0..10_000 {
+            old_counts[*old.random(&mut rng).unwrap().0] += 1;
+            new_counts[*new.random(&mut rng).unwrap().0] += 1;
+        }
+        assert!(old_counts
+            .iter()
+            .all(|count| (2_300..2_700).contains(count)));
+        assert_eq!(new_counts[..2], [0, 0]);
+        assert!(new_counts[2..]
+            .iter()
+            .all(|count| (4_700..5_300).contains(count)));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let tree = (0..1_000)
+            .map(|k| (k, k))
+            .collect::<PersistentRandomTree<_, _>>();
+        let handles = (0..4)
+            .map(|t| {
+                let snapshot = tree.clone();
+                std::thread::spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(t);
+                    let mine = snapshot.insert(1_000 + t, 0);
+                    (0..1_000)
+                        .map(|_| *snapshot.random(&mut rng).unwrap().0)
+                        .all(|k| k < 1_000)
+                        && mine.len() == 1_001
+                })
+            })
+            .collect::<Vec<_>>();
+        assert!(handles.into_iter().all(|handle| handle.join().unwrap()));
+        assert_eq!(tree.len(), 1_000);
+    }
+}
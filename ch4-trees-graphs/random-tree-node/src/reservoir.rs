@@ -0,0 +1,278 @@
+// Reservoir sampling: picking items uniformly, or by weight, from a stream
+// whose length isn't known in advance, in one pass and O(k) memory.
+// The order of the returned items is unspecified.
+use rand::{distributions::OpenClosed01, Rng};
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+// Picks one item, or None if there are none.
+// O(n), with one random number per item.
+pub fn choose<I, R>(source: I, rng: &mut R) -> Option<I::Item>
+where
+    I: IntoIterator,
+    R: Rng + ?Sized,
+{
+    let mut ret = None;
+    let mut count: usize = 0;
+    for item in source {
+        count += 1;
+        if rng.gen_range(0, count) == 0 {
+            ret = Some(item);
+        }
+    }
+    ret
+}
+
+// Picks min(k, n) distinct items, each with the same probability.
+// Vitter's Algorithm R.
+// O(n), with one random number per item.
+pub fn sample<I, R>(source: I, k: usize, rng: &mut R) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    R: Rng + ?Sized,
+{
+    let mut source = source.into_iter();
+    let mut reservoir = source.by_ref().take(k).collect::<Vec<_>>();
+    if reservoir.len() < k {
+        return reservoir;
+    }
+    for (seen, item) in (k + 1..).zip(source) {
+        let i = rng.gen_range(0, seen);
+        if i < k {
+            reservoir[i] = item;
+        }
+    }
+    reservoir
+}
+
+// Same as sample, but only draws random numbers for the items that make it
+// into the reservoir, and skips the ones in between with nth.
+// Li's Algorithm L.
+// O(n) to go through the items, but only O(k * (1 + log(n / k))) random
+// numbers.
+pub fn sample_skipping<I, R>(source: I, k: usize, rng: &mut R) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    R: Rng + ?Sized,
+{
+    let mut source = source.into_iter();
+    let mut reservoir = source.by_ref().take(k).collect::<Vec<_>>();
+    if k == 0 || reservoir.len() < k {
+        return reservoir;
+    }
+    // w is the largest of k uniform numbers, one per reservoir item. An item
+    // gets in iff its own number is below w, so the gap until the next one
+    // is geometrically distributed.
+    let mut w = (open01(rng).ln() / k as f64).exp();
+    loop {
+        let skip = (open01(rng).ln() / (1.0 - w).ln()).floor();
+        // Saturates for huge skips, which run past the end anyway.
+        match source.nth(skip as usize) {
+            None => return reservoir,
+            Some(item) => reservoir[rng.gen_range(0, k)] = item,
+        }
+        w *= (open01(rng).ln() / k as f64).exp();
+    }
+}
+
+// Picks min(k, m) distinct items, where m is the number of items with a
+// weight above 0. Each pick is made in proportion to weight, among the
+// items not picked yet.
+// Weights have to be finite and non-negative.
+// Efraimidis and Spirakis' A-Res: each item gets the key u^(1 / weight), and
+// the k largest keys win.
+// O(n * log(k)), with one random number per item.
+pub fn sample_weighted<I, F, R>(source: I, k: usize, mut weight: F, rng: &mut R) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> f64,
+    R: Rng + ?Sized,
+{
+    let mut reservoir = BinaryHeap::with_capacity(k);
+    if k == 0 {
+        return Vec::new();
+    }
+    for item in source {
+        let w = checked(weight(&item));
+        if w == 0.0 {
+            continue;
+        }
+        let key = open01(rng).ln() / w;
+        if reservoir.len() < k {
+            reservoir.push(Keyed { key, item });
+        } else if key > reservoir.peek().unwrap().key {
+            reservoir.pop();
+            reservoir.push(Keyed { key, item });
+        }
+    }
+    reservoir.into_iter().map(|keyed| keyed.item).collect()
+}
+
+// Same as sample_weighted, but jumps over the items that wouldn't make it
+// into the reservoir, by drawing how much weight to skip.
+// Efraimidis and Spirakis' A-ExpJ.
+// O(n) to go through the items, plus O(log(k)) for each one that makes it
+// into the reservoir, which is O(k * log(n / k)) of them on average.
+pub fn sample_weighted_skipping<I, F, R>(
+    source: I,
+    k: usize,
+    mut weight: F,
+    rng: &mut R,
+) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> f64,
+    R: Rng + ?Sized,
+{
+    let mut reservoir = BinaryHeap::with_capacity(k);
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut source = source.into_iter();
+    while reservoir.len() < k {
+        match source.next() {
+            None => break,
+            Some(item) => {
+                let w = checked(weight(&item));
+                if w > 0.0 {
+                    let key = open01(rng).ln() / w;
+                    reservoir.push(Keyed { key, item });
+                }
+            }
+        }
+    }
+    if reservoir.len() < k {
+        return reservoir.into_iter().map(|keyed| keyed.item).collect();
+    }
+
+    // Keys are kept as logarithms, so threshold is ln(T_w) in the paper.
+    let mut threshold = reservoir.peek().unwrap().key;
+    let mut to_skip = open01(rng).ln() / threshold;
+    for item in source {
+        let w = checked(weight(&item));
+        to_skip -= w;
+        if to_skip <= 0.0 && w > 0.0 {
+            // The new key is uniform in (T_w^w, 1], since it beats the
+            // threshold.
+            let floor = (w * threshold).exp();
+            let u = floor + (1.0 - floor) * open01(rng);
+            reservoir.pop();
+            reservoir.push(Keyed {
+                key: u.ln() / w,
+                item,
+            });
+            threshold = reservoir.peek().unwrap().key;
+            to_skip = open01(rng).ln() / threshold;
+        }
+    }
+    reservoir.into_iter().map(|keyed| keyed.item).collect()
+}
+
+// Uniform in (0, 1], so that its logarithm is finite.
+fn open01<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    rng.sample(OpenClosed01)
+}
+
+fn checked(weight: f64) -> f64 {
+    assert!(weight.is_finite() && weight >= 0.0, "invalid weight");
+    weight
+}
+
+// An item with its key, ordered so that the smallest key is on top of a
+// BinaryHeap.
+struct Keyed<T> {
+    key: f64,
+    item: T,
+}
+
+impl<T> PartialEq for Keyed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Keyed<T> {}
+
+impl<T> PartialOrd for Keyed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Keyed<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.total_cmp(&self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    type Sampler = fn(std::ops::Range<usize>, usize, &mut StdRng) -> Vec<usize>;
+
+    #[test]
+    fn uniform_samples() {
+        let mut rng = StdRng::seed_from_u64(18);
+        let samplers: [Sampler; 2] = [sample, sample_skipping];
+        for sampler in samplers.iter() {
+            assert!(sampler(0..10, 0, &mut rng).is_empty());
+            let mut short = sampler(0..3, 5, &mut rng);
+            short.sort_unstable();
+            assert_eq!(short, vec![0, 1, 2]);
+
+            // Every item should make it in a quarter of the time.
+            let mut counts = [0; 20];
+            for _ in 0..10_000 {
+                let mut picked = sampler(0..20, 5, &mut rng);
+                assert_eq!(picked.len(), 5);
+                for i in picked.iter() {
+                    counts[*i] += 1;
+                }
+                picked.sort_unstable();
+                picked.dedup();
+                assert_eq!(picked.len(), 5);
+            }
+            assert!(counts.iter().all(|count| (2_300..2_700).contains(count)));
+        }
+    }
+
+    #[test]
+    fn choose_one() {
+        let mut rng = StdRng::seed_from_u64(18);
+        assert_eq!(choose(Vec::<u8>::new(), &mut rng), None);
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            counts[choose(0..4, &mut rng).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|count| (2_300..2_700).contains(count)));
+    }
+
+    #[test]
+    fn weighted_samples() {
+        let mut rng = StdRng::seed_from_u64(18);
+        type WeightedSampler = fn(Vec<f64>, usize, fn(&f64) -> f64, &mut StdRng) -> Vec<f64>;
+        let samplers: [WeightedSampler; 2] = [sample_weighted, sample_weighted_skipping];
+        for sampler in samplers.iter() {
+            let weights = vec![0.0, 1.0, 3.0, 1.0];
+            assert!(sampler(weights.clone(), 0, |w| *w, &mut rng).is_empty());
+            let mut everything = sampler(weights.clone(), 10, |w| *w, &mut rng);
+            everything.sort_by(f64::total_cmp);
+            assert_eq!(everything, vec![1.0, 1.0, 3.0]);
+
+            // A single pick is proportional to weight.
+            let mut counts = [0; 3];
+            for _ in 0..10_000 {
+                let picked = sampler(vec![1.0, 3.0, 0.0, 1.0, 0.0], 1, |w| *w, &mut rng);
+                counts[picked[0] as usize - 1] += 1;
+            }
+            assert!((3_700..4_300).contains(&counts[0]));
+            assert!((5_700..6_300).contains(&counts[2]));
+
+            // Heavy items crowd out light ones, even from far back.
+            let stream = (0..10_000).map(|i| if i % 1_000 == 999 { 1e9 } else { 1.0 });
+            let picked = sampler(stream.collect(), 10, |w| *w, &mut rng);
+            assert_eq!(picked.iter().filter(|w| **w == 1e9).count(), 10);
+        }
+    }
+}
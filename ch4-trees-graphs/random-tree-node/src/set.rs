@@ -0,0 +1,149 @@
+use crate::{IntoIter, Keys, RandomTree};
+use rand::Rng;
+use std::borrow::Borrow;
+use std::iter::{FromIterator, Map};
+
+// A RandomTree without values.
+#[derive(Debug)]
+pub struct RandomSet<K> {
+    tree: RandomTree<K, ()>,
+}
+
+impl<K> RandomSet<K>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            tree: RandomTree::new(),
+        }
+    }
+
+    // Returns whether the key is new.
+    // O(log(n))
+    pub fn insert(&mut self, key: K) -> bool {
+        self.tree.insert(key, ()).is_none()
+    }
+
+    // O(log(n))
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.has(key)
+    }
+
+    // Returns whether the key was present.
+    // O(log(n))
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.tree.remove(key).is_some()
+    }
+
+    // Returns None iff the set is empty.
+    // O(log(n))
+    pub fn random<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&K> {
+        self.tree.random(rng).map(|(key, _)| key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // Iterates in key order.
+    pub fn iter(&self) -> Keys<'_, K, ()> {
+        self.tree.keys()
+    }
+}
+
+impl<K> Default for RandomSet<K>
+where
+    K: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> FromIterator<K> for RandomSet<K>
+where
+    K: Ord,
+{
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        Self {
+            tree: iter.into_iter().map(|key| (key, ())).collect(),
+        }
+    }
+}
+
+impl<K> Extend<K> for RandomSet<K>
+where
+    K: Ord,
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.tree.extend(iter.into_iter().map(|key| (key, ())));
+    }
+}
+
+impl<K> IntoIterator for RandomSet<K> {
+    type Item = K;
+    type IntoIter = Map<IntoIter<K, ()>, fn((K, ())) -> K>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tree.into_iter().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K> IntoIterator for &'a RandomSet<K>
+where
+    K: Ord,
+{
+    type Item = &'a K;
+    type IntoIter = Keys<'a, K, ()>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::thread_rng;
+
+    #[test]
+    fn set_operations() {
+        let mut rng = thread_rng();
+        let mut set = RandomSet::default();
+        assert!(set.is_empty());
+        assert_eq!(set.random(&mut rng), None);
+
+        assert!(set.insert("b"));
+        assert!(set.insert("a"));
+        assert!(!set.insert("a"));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&"a"));
+        assert!(["a", "b"].contains(set.random(&mut rng).unwrap()));
+
+        assert!(set.remove(&"a"));
+        assert!(!set.remove(&"a"));
+        assert_eq!(set.random(&mut rng), Some(&"b"));
+    }
+
+    #[test]
+    fn set_iterators() {
+        let mut set = (0..10).rev().collect::<RandomSet<_>>();
+        set.extend(5..15);
+        assert_eq!(set.len(), 15);
+        assert!((&set).into_iter().copied().eq(0..15));
+        assert!(set.into_iter().eq(0..15));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::Weight;
+use std::ops::Add;
+
+// A value kept up to date in every node, summarizing the entries of its
+// subtree. combine has to be associative, with empty as its identity, but
+// it doesn't need to be commutative: the left subtree always comes first.
+// RandomTree<K, V, S> recomputes it on every insert, remove and rotation,
+// which is O(1) per node touched.
+pub trait Summary<K, V>: Clone {
+    fn empty() -> Self;
+
+    fn of(key: &K, value: &V) -> Self;
+
+    fn combine(&self, other: &Self) -> Self;
+}
+
+// The default: summarizes nothing, and costs nothing.
+impl<K, V> Summary<K, V> for () {
+    fn empty() -> Self {}
+
+    fn of(_: &K, _: &V) -> Self {}
+
+    fn combine(&self, _: &Self) -> Self {}
+}
+
+// Both summaries at once.
+impl<K, V, A, B> Summary<K, V> for (A, B)
+where
+    A: Summary<K, V>,
+    B: Summary<K, V>,
+{
+    fn empty() -> Self {
+        (A::empty(), B::empty())
+    }
+
+    fn of(key: &K, value: &V) -> Self {
+        (A::of(key, value), B::of(key, value))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        (self.0.combine(&other.0), self.1.combine(&other.1))
+    }
+}
+
+// For summaries that can weigh a subtree, so that entries can be picked in
+// proportion to it with RandomTree::random_by_measure.
+// Measures have to be finite, non-negative and additive.
+pub trait Measure {
+    fn measure(&self) -> f64;
+}
+
+// Number of entries, e.g. to count the keys in a range with summarize.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Count(pub usize);
+
+impl<K, V> Summary<K, V> for Count {
+    fn empty() -> Self {
+        Count(0)
+    }
+
+    fn of(_: &K, _: &V) -> Self {
+        Count(1)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Count(self.0 + other.0)
+    }
+}
+
+impl Measure for Count {
+    fn measure(&self) -> f64 {
+        self.0 as f64
+    }
+}
+
+// Sum of the values. Default has to be zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sum<V>(pub V);
+
+impl<K, V> Summary<K, V> for Sum<V>
+where
+    V: Clone + Default + Add<Output = V>,
+{
+    fn empty() -> Self {
+        Sum(V::default())
+    }
+
+    fn of(_: &K, value: &V) -> Self {
+        Sum(value.clone())
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Sum(self.0.clone() + other.0.clone())
+    }
+}
+
+// Lets sums of numbers weigh entries, e.g. sums of counts.
+macro_rules! sum_measure {
+    ($($number:ty),*) => {
+        $(
+            impl Measure for Sum<$number> {
+                fn measure(&self) -> f64 {
+                    self.0 as f64
+                }
+            }
+        )*
+    };
+}
+
+sum_measure!(usize, u32, u64, f64);
+
+// Largest value, or None if there are no entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Max<V>(pub Option<V>);
+
+impl<K, V> Summary<K, V> for Max<V>
+where
+    V: Clone + PartialOrd,
+{
+    fn empty() -> Self {
+        Max(None)
+    }
+
+    fn of(_: &K, value: &V) -> Self {
+        Max(Some(value.clone()))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) if b > a => other.clone(),
+            (None, _) => other.clone(),
+            _ => self.clone(),
+        }
+    }
+}
+
+// Smallest value, or None if there are no entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Min<V>(pub Option<V>);
+
+impl<K, V> Summary<K, V> for Min<V>
+where
+    V: Clone + PartialOrd,
+{
+    fn empty() -> Self {
+        Min(None)
+    }
+
+    fn of(_: &K, value: &V) -> Self {
+        Min(Some(value.clone()))
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) if b < a => other.clone(),
+            (None, _) => other.clone(),
+            _ => self.clone(),
+        }
+    }
+}
+
+// Sum of the weights the values carry, which RandomTree::random_weighted
+// goes by. Invalid weights count as 0; see Weight.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TotalWeight(pub f64);
+
+impl<K, V> Summary<K, V> for TotalWeight
+where
+    V: Weight,
+{
+    fn empty() -> Self {
+        TotalWeight(0.0)
+    }
+
+    fn of(_: &K, value: &V) -> Self {
+        let weight = value.weight();
+        TotalWeight(if weight.is_finite() && weight >= 0.0 {
+            weight
+        } else {
+            0.0
+        })
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        TotalWeight(self.0 + other.0)
+    }
+}
+
+impl Measure for TotalWeight {
+    fn measure(&self) -> f64 {
+        self.0
+    }
+}